@@ -1,5 +1,6 @@
 use std::{
     collections::{HashMap, HashSet},
+    ffi::OsString,
     path::{Path, PathBuf},
     time::Duration,
 };
@@ -7,11 +8,40 @@ use std::{
 use itertools::Itertools;
 use tokio::time::Instant;
 
+mod cgroup;
 mod distro_specific;
+mod libraries;
+mod limits;
+mod loader;
+mod output;
+mod queue;
+mod registry;
+mod run_registry;
+mod safe_launch;
+mod scratch;
+mod submission;
+
+pub use submission::{RunRequest, RunStdin, SubmissionError};
+
+/// The directory inside the sandbox holding the `FILES_ROOT` of the runs, each run mounting its
+/// files dependencies under a random directory of it (see [`Run::files_root`])
+const FILES_DIR: &str = "/files";
+
+/// Where the submitted files are mounted inside the sandbox, given in `SUBMITTED_ROOT`
+const SUBMITTED_ROOT: &str = "/submitted";
+
+/// The writable directory holding the trace file inside the sandbox, the file itself is given in
+/// `TRACE_FILE`
+const TRACE_DIR: &str = "/trace";
+
+/// The name of the trace file, inside of [`TRACE_DIR`]
+const TRACE_FILE_NAME: &str = "trace";
+
+/// The maximum number of bytes of the trace that are kept, the rest is discarded
+const MAX_TRACE_SIZE: u64 = 1 << 20;
 
 /// An instance of a runner.
 /// This will allow the spawing of [`Run`]s
-#[derive(Debug)]
 struct Runner {
     /// The id of the runner, not equal on different instanciation of the program
     id: uuid::Uuid,
@@ -44,11 +74,37 @@ struct Runner {
     /// These dependencies will be created by distro specific function (and in case of an unknown
     /// distro it'll be a sensible default) that will specify which path to map
     absolute_file_deps: HashSet<PathBuf>,
+
+    /// The resources limits of the runner: the ones of the manifest, completed by the ones of its
+    /// resource profile
+    limits: limits::ResourceLimits,
+
+    /// The callbacks called each time one of the [`Run`]s of this runner is over
+    on_complete: Vec<CompletionCallback>,
 }
 
-#[derive(Debug)]
+/// A callback called with a [`Run`] that is over (see [`Runner::on_complete`])
+type CompletionCallback = Box<dyn Fn(&Run) + Send + Sync>;
+
+#[derive(Debug, serde::Serialize)]
 struct RunOutput {
-    trace: String,
+    /// The trace written by the runner (see [`RunnerManifest::show_trace`])
+    trace: output::CapturedOutput,
+
+    /// The stdout of the sandbox, either as bytes or as the file it was written to (see
+    /// [`output::OutputDestination`])
+    stdout: output::CapturedOutput,
+
+    /// The stderr of the sandbox, either as bytes or as the file it was written to (see
+    /// [`output::OutputDestination`])
+    stderr: output::CapturedOutput,
+
+    /// How [`RunOutput::stdout`] and [`RunOutput::stderr`] are converted to text
+    encoding: output::OutputEncoding,
+
+    /// Whether [`RunOutput::stdout`] or [`RunOutput::stderr`] were cut at
+    /// [`RunnerManifest::max_output_bytes`]
+    truncated: bool,
 
     /// Status string.
     ///
@@ -58,6 +114,220 @@ struct RunOutput {
 
     /// Wether the run was a success or not (aka exit code 0);
     successful: bool,
+
+    /// Whether the sandbox was killed for going over its memory limit, only detected when the
+    /// run is placed inside a cgroup (see [`Run::in_cgroup`]). The status is then `Out of memory`
+    out_of_memory: bool,
+
+    /// The namespaces that were effectively unshared for the sandbox.
+    ///
+    /// This can differ from the requested namespaces if some of them couldn't be created on the
+    /// host (when using the `_TRY` variants), meaning that the isolation was degraded
+    #[serde(serialize_with = "serialize_namespaces")]
+    namespaces: kincir_bwrap::NsFlags,
+
+    /// The metadata given to the [`Run`] (see [`Run::with_metadata`])
+    metadata: HashMap<String, String>,
+}
+
+/// Serialize the namespaces as a list of their names
+fn serialize_namespaces<S: serde::Serializer>(
+    namespaces: &kincir_bwrap::NsFlags,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(namespaces.iter_names().map(|(name, _)| name))
+}
+
+impl RunOutput {
+    /// The stdout of the sandbox as valid UTF-8, or `None` if it was written to a file
+    fn stdout_text(&self) -> Option<std::borrow::Cow<'_, str>> {
+        self.stdout.text(self.encoding)
+    }
+
+    /// The stderr of the sandbox as valid UTF-8, or `None` if it was written to a file
+    fn stderr_text(&self) -> Option<std::borrow::Cow<'_, str>> {
+        self.stderr.text(self.encoding)
+    }
+
+    /// The trace as valid UTF-8
+    fn trace_text(&self) -> Option<std::borrow::Cow<'_, str>> {
+        self.trace.text(self.encoding)
+    }
+}
+
+impl std::fmt::Debug for Runner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Runner")
+            .field("id", &self.id)
+            .field("manifest", &self.manifest)
+            .field("bin_deps", &self.bin_deps)
+            .field("file_deps", &self.file_deps)
+            .field("absolute_file_deps", &self.absolute_file_deps)
+            .field("limits", &self.limits)
+            .field("on_complete", &self.on_complete.len())
+            .finish()
+    }
+}
+
+impl Runner {
+    /// Register a callback that will be called each time a [`Run`] of this runner is over, be it
+    /// completed, timed out or cancelled. The outcome is the [`Run::state`] of the given run.
+    ///
+    /// The callbacks are called synchronously when the run ends, so they must be fast: anything
+    /// slow (such as notifying an external service) should be sent to another task
+    pub fn on_complete(&mut self, callback: impl Fn(&Run) + Send + Sync + 'static) -> &mut Self {
+        self.on_complete.push(Box::new(callback));
+        self
+    }
+
+    /// Bind the binary dependencies at `/bin/<name>` inside the given sandbox.
+    ///
+    /// A dependency that is a symlink on the host (for example `/usr/bin/cc -> gcc`) would be
+    /// useless without its target, so the symlink is resolved: the real target is bound at the
+    /// same path inside the sandbox, and `/bin/<name>` is created as a symlink to it (meaning that
+    /// tools looking at their `argv[0]` still work).
+    fn bind_bin_deps(&self, command: &mut kincir_bwrap::BwrapCommand<'_>) -> std::io::Result<()> {
+        for (name, host) in self.bin_deps.iter().sorted() {
+            let guest = Path::new("/bin").join(name);
+            let target = std::fs::canonicalize(host)?;
+            if &target == host {
+                command.bind_read_only(host, guest);
+            } else {
+                command
+                    .bind_read_only(&target, &target)
+                    .symlink(&target, guest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Launch a new [`Run`] of this runner, with the submitted files of `request`.
+    ///
+    /// The entry is started inside the sandbox with the environment documented at
+    /// [`RunnerManifest::entry`]. This must be called from a tokio runtime, and the sandbox is
+    /// killed if the returned [`Run`] is dropped while still running.
+    ///
+    /// This is the same as launching the entry as the first step of a new run (see
+    /// [`Runner::launch_step`])
+    pub fn launch(&self, request: &RunRequest) -> std::io::Result<Run> {
+        let mut run = self.new_run();
+        let (program, args) = self.entry(&run);
+        self.launch_step(&mut run, request, program, args)?;
+        Ok(run)
+    }
+
+    /// Create a new [`Run`] of this runner, without launching anything
+    pub fn new_run(&self) -> Run {
+        Run::new(self)
+    }
+
+    /// Launch the next step of `run`: `program` (a path inside of the sandbox, such as
+    /// `/bin/<bin_dep>`) with `args`, in the same sandbox and environment as the entry.
+    ///
+    /// The steps of a run share its scratch directory (see [`RunnerManifest::scratch_dir`]), so
+    /// a step can use what the previous ones wrote there, for example compiling then executing a
+    /// program. Each step is awaited with [`Run::await_completion`], which gives its own
+    /// [`RunOutput`]. This must be called from a tokio runtime.
+    ///
+    /// A request that doesn't pass [`RunRequest::verify`] (with
+    /// [`RunnerManifest::max_submitted_size`]) fails with [`std::io::ErrorKind::InvalidInput`],
+    /// as well as a `run` of another runner, or one whose previous step is still running
+    pub fn launch_step(
+        &self,
+        run: &mut Run,
+        request: &RunRequest,
+        program: impl Into<OsString>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+    ) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let invalid = |e: Box<dyn std::error::Error + Send + Sync>| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+        };
+        request
+            .verify(self.manifest.max_submitted_size)
+            .map_err(|e| invalid(e.into()))?;
+        if run.runner_id != self.id {
+            return Err(invalid("the run belongs to another runner".into()));
+        }
+        if run.child.is_some() {
+            return Err(invalid(
+                "the previous step of the run is still running".into(),
+            ));
+        }
+        let args = args.into_iter().map(Into::into).collect::<Vec<_>>();
+        run.stdin = request.stdin().cloned();
+        // created close-on-exec so that no other child inherits it, only the write end is then
+        // inherited by bwrap
+        let (info, info_writer) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)?;
+        nix::fcntl::fcntl(
+            info_writer.as_raw_fd(),
+            nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::empty()),
+        )?;
+        let command = self
+            .launch_command(run, request, program.into(), &args)?
+            .info_fd(&info_writer)
+            .command()
+            .map_err(|e| invalid(e.into()))?;
+        run.spawn(command)?;
+        // closed so that reading the info ends if bwrap exits without writing it
+        drop(info_writer);
+        run.probe_namespaces(info);
+        Ok(())
+    }
+
+    /// The program and the arguments launching the entry for the given `run`
+    fn entry(&self, run: &Run) -> (OsString, Vec<OsString>) {
+        let entry = run.files_root.join(&self.manifest.entry).into_os_string();
+        match &self.manifest.interpreter {
+            Some(interpreter) => (
+                Path::new("/bin").join(interpreter).into_os_string(),
+                vec![entry],
+            ),
+            None => (entry, Vec::new()),
+        }
+    }
+
+    /// Create the sandbox command launching `program` with `args` for the given `run`
+    fn launch_command(
+        &self,
+        run: &mut Run,
+        request: &RunRequest,
+        program: OsString,
+        args: &[OsString],
+    ) -> std::io::Result<kincir_bwrap::BwrapCommand<'static>> {
+        let mut command = run.step_command(program)?;
+        for arg in args {
+            command.arg(arg);
+        }
+        let trace = scratch::ScratchDir::create(TRACE_DIR)?;
+        std::fs::File::create(trace.host().join(TRACE_FILE_NAME))?;
+        trace.bind(&mut command);
+        command
+            .clear_env(true)
+            // set first, so that the reserved variables always win
+            .envs(&self.manifest.env)
+            .add_env("PATH", "/bin")
+            .add_env("FILES_ROOT", &run.files_root)
+            .add_env("SUBMITTED_ROOT", SUBMITTED_ROOT)
+            .add_env("TRACE_FILE", trace.guest().join(TRACE_FILE_NAME))
+            .add_namespace_flags(kincir_bwrap::NsFlags::ALL | self.manifest.network_flag())
+            .die_with_parent(true)
+            .new_session(true)
+            .proc_dir("/proc")
+            .dev_dir("/dev");
+        for path in self.absolute_file_deps.iter().sorted() {
+            command.bind_read_only(path, path);
+        }
+        self.bind_bin_deps(&mut command)?;
+        run.safe_launch = Some(safe_launch::install(&mut command)?);
+        for (host, guest) in self.file_deps.iter().sorted() {
+            command.bind_read_only(host, run.files_root.join(guest));
+        }
+        run.submitted = Some(request.install(&mut command, SUBMITTED_ROOT)?);
+        run.trace = Some(trace);
+        Ok(command)
+    }
 }
 
 /// The State of the [`Run`]
@@ -75,6 +345,29 @@ enum RunState {
     /// The [`Run`] timed out, and as such was killed. No [`RunOutput`] will be given in this case (could
     /// be corrupted)
     TimedOut,
+
+    /// The [`Run`] was cancelled (see [`Run::cancel_handle`]), and as such was killed. Like for
+    /// [`RunState::TimedOut`], no [`RunOutput`] will be given
+    Cancelled,
+
+    /// Waiting for the [`Run`] failed (see [`Run::await_completion`]) with the given error, and
+    /// as such it was killed. No [`RunOutput`] will be given
+    Failed(String),
+}
+
+/// Cancel a [`Run`] from another task, while it is being awaited (see [`Run::cancel_handle`])
+#[derive(Debug, Clone)]
+pub struct CancelHandle(std::sync::Arc<tokio::sync::Notify>);
+
+impl CancelHandle {
+    /// Ask for the run to be cancelled: its whole process group is killed, and it ends up
+    /// [`RunState::Cancelled`].
+    ///
+    /// A run that is over (completed or timed out) stays as is. Cancelling a run that isn't awaited
+    /// yet takes effect as soon as [`Run::await_completion`] is called
+    pub fn cancel(&self) {
+        self.0.notify_one();
+    }
 }
 
 /// A single run, associated with a specific runner.
@@ -89,8 +382,487 @@ struct Run {
     /// The current state of the [`Run`]
     state: RunState,
 
+    /// When the run was over (completed, timed out or cancelled), used to forget about old runs
+    /// (see [`run_registry::RunRegistry::prune`])
+    finished_at: Option<Instant>,
+
     /// the runner associated with the [`Run`]
     runner_id: uuid::Uuid,
+
+    /// Where the stdout/stderr of the sandbox will be written
+    output: output::OutputDestination,
+
+    /// The maximum size of each captured output, taken from the runner's manifest
+    max_output_bytes: u64,
+
+    /// Whether the captured output is stored compressed, taken from the runner's manifest
+    #[cfg(feature = "compression")]
+    compress_output: bool,
+
+    /// The resources limits of the sandbox, taken from the runner's manifest and completed by the
+    /// [`limits::ResourceLimits::DEFAULTS`]
+    limits: limits::ResourceLimits,
+
+    /// The time after which the sandbox is killed, taken from the runner's manifest
+    timeout: Duration,
+
+    /// Where the files dependencies are mounted inside the sandbox, given in `FILES_ROOT`.
+    ///
+    /// This is a random directory inside of [`FILES_DIR`], unique to the run: the random part is
+    /// a v4 uuid, which is generated from the OS' CSPRNG
+    files_root: PathBuf,
+
+    /// Where the scratch directory should be mounted, taken from the runner's manifest
+    scratch_dir: Option<PathBuf>,
+
+    /// The scratch directory of the run, created when the first step is set up
+    scratch: Option<scratch::ScratchDir>,
+
+    /// The directory holding the trace file of the run, created when it is launched
+    trace: Option<scratch::ScratchDir>,
+
+    /// The directory holding the submitted files of the run, created when it is launched
+    submitted: Option<scratch::ScratchDir>,
+
+    /// The directory holding the `safe-launch` wrapper of the run (see
+    /// [`RunnerManifest::entry`]), created when it is launched
+    safe_launch: Option<scratch::ScratchDir>,
+
+    /// What is written to the stdin of the sandbox, taken from the [`RunRequest`]
+    stdin: Option<RunStdin>,
+
+    /// The sandbox process, once the run is launched
+    child: Option<tokio::process::Child>,
+
+    /// The cgroup the sandbox is placed in, if any (see [`Run::in_cgroup`])
+    cgroup: Option<cgroup::Cgroup>,
+
+    /// Finds the namespaces effectively unshared while the sandbox runs (see
+    /// [`Run::probe_namespaces`])
+    namespaces: Option<tokio::task::JoinHandle<std::io::Result<kincir_bwrap::NsFlags>>>,
+
+    /// Notified when the run is cancelled (see [`Run::cancel_handle`])
+    cancel: CancelHandle,
+
+    /// How the captured output is converted to text, taken from the runner's manifest
+    encoding: output::OutputEncoding,
+
+    /// The size of the writable `/tmp`, taken from the runner's manifest
+    write_quota: Option<usize>,
+
+    /// The priority of the run when waiting for a slot in the [`queue::RunQueue`], higher first
+    priority: i32,
+
+    /// Arbitrary metadata (submission id, user, ...) given back in the [`RunOutput`].
+    ///
+    /// This doesn't change anything to the execution of the run
+    metadata: HashMap<String, String>,
+}
+
+impl Run {
+    /// Create a new [`Run`] of the given runner. The run isn't launched
+    fn new(runner: &Runner) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            show_trace: runner.manifest.show_trace,
+            state: RunState::NotLaunched,
+            finished_at: None,
+            runner_id: runner.id,
+            output: output::OutputDestination::default(),
+            max_output_bytes: runner.manifest.max_output_bytes,
+            #[cfg(feature = "compression")]
+            compress_output: runner.manifest.compress_output,
+            limits: runner.limits.with_defaults(),
+            timeout: runner.manifest.timeout,
+            files_root: Path::new(FILES_DIR).join(uuid::Uuid::new_v4().simple().to_string()),
+            scratch_dir: runner.manifest.scratch_dir.clone(),
+            scratch: None,
+            trace: None,
+            submitted: None,
+            safe_launch: None,
+            stdin: None,
+            child: None,
+            cgroup: None,
+            namespaces: None,
+            cancel: CancelHandle(std::sync::Arc::default()),
+            encoding: runner.manifest.output_encoding,
+            write_quota: runner.manifest.write_quota,
+            priority: 0,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Set the priority of the run (0 by default).
+    ///
+    /// When more runs are waiting than there are free slots in the [`queue::RunQueue`], the ones
+    /// with the highest priority start first (for example interactive runs before batch jobs)
+    pub fn with_priority(&mut self, priority: i32) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Wait for a slot in the given queue, according to the priority of the run
+    pub async fn acquire(&self, queue: &std::sync::Arc<queue::RunQueue>) -> queue::RunPermit {
+        queue.acquire(self.priority).await
+    }
+
+    /// Enter the given queue according to the priority of the run, without waiting for the slot
+    /// (see [`queue::RunQueue::enqueue`])
+    pub fn enqueue(&self, queue: &std::sync::Arc<queue::RunQueue>) -> queue::QueuedRun {
+        queue.enqueue(self.priority)
+    }
+
+    /// Attach a metadata to the run, it will be given back as-is in the [`RunOutput`]
+    pub fn with_metadata(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Place the sandbox in a new cgroup (v2) created inside of `parent`, with the limits of the
+    /// run written into it (see [`limits::ResourceLimits::apply_to_cgroup`]). This must be called
+    /// before the run is launched.
+    ///
+    /// Inside a cgroup, the memory limit is enforced with `memory.max` instead of `RLIMIT_AS`,
+    /// meaning that a run going over it is killed and reported as out of memory (see
+    /// [`RunOutput::out_of_memory`])
+    pub fn in_cgroup(&mut self, parent: impl AsRef<Path>) -> std::io::Result<&mut Self> {
+        let cgroup = cgroup::Cgroup::create(parent, &format!("kincir-{}", self.id))?;
+        self.limits.apply_to_cgroup(&cgroup)?;
+        self.cgroup = Some(cgroup);
+        Ok(self)
+    }
+
+    /// Spawn the sandbox `command` of the run, with the limits and the output destination of the
+    /// run.
+    ///
+    /// The sandbox gets its own process group, so that it can be killed with everything it
+    /// spawned (see [`Run::await_completion`])
+    fn spawn(&mut self, mut command: std::process::Command) -> std::io::Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.install(&mut command)?;
+        }
+        self.rlimits().install(&mut command);
+        let (stdout, stderr) = self.output.stdio()?;
+        let stdin = match self.stdin {
+            Some(_) => std::process::Stdio::piped(),
+            None => std::process::Stdio::null(),
+        };
+        command
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(stderr)
+            .process_group(0);
+        let child = tokio::process::Command::from(command)
+            .kill_on_drop(true)
+            .spawn()?;
+        self.child = Some(child);
+        self.state = RunState::Running(Instant::now());
+        Ok(())
+    }
+
+    /// Read the info written by bwrap on `info` (see [`kincir_bwrap::BwrapCommand::info_fd`]) in
+    /// the background, and find the namespaces of the sandboxed process while it still runs.
+    ///
+    /// The pid of bwrap itself can't be used, since it stays in the namespaces of the host
+    fn probe_namespaces(&mut self, info: std::os::fd::OwnedFd) {
+        self.namespaces = Some(tokio::task::spawn_blocking(move || {
+            let info = kincir_bwrap::SandboxInfo::read(std::fs::File::from(info))?;
+            kincir_bwrap::NsFlags::unshared_by(info.child_pid)
+        }));
+    }
+
+    /// A handle cancelling the run, usable while the run itself is borrowed by
+    /// [`Run::await_completion`] (for example from another task)
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Wait for the launched run to be over, and call the [`Runner::on_complete`] callbacks of
+    /// its runner.
+    ///
+    /// If the sandbox is still running once the timeout of the runner is reached (counting from
+    /// the launch), its whole process group is killed and the run ends up
+    /// [`RunState::TimedOut`], without any output. The same goes if the run is cancelled, ending
+    /// up [`RunState::Cancelled`].
+    ///
+    /// Only the first of the completion, the timeout and the cancellation is taken into account.
+    ///
+    /// The stdin of the sandbox (see [`RunRequest::with_stdin`]) is written while waiting. If
+    /// this fails (or reading the output does), the sandbox is killed too and the run ends up
+    /// [`RunState::Failed`] before the error is returned
+    pub async fn await_completion(&mut self, runner: &Runner) -> std::io::Result<()> {
+        let (Some(mut child), RunState::Running(started)) = (self.child.take(), &self.state) else {
+            return Err(std::io::Error::other("the run isn't running"));
+        };
+        let deadline = *started + self.timeout;
+        match self.wait(runner, &mut child, deadline).await {
+            Ok(state) => {
+                self.finish(runner, state);
+                Ok(())
+            }
+            Err(e) => {
+                kill_process_group(&child);
+                if let Err(e) = child.wait().await {
+                    log::warn!("failed to wait for the failed run {}: {e}", self.id);
+                }
+                self.finish(runner, RunState::Failed(e.to_string()));
+                Err(e)
+            }
+        }
+    }
+
+    /// Wait for the sandbox `child` to be over, giving the final state of the run (see
+    /// [`Run::await_completion`])
+    async fn wait(
+        &mut self,
+        runner: &Runner,
+        child: &mut tokio::process::Child,
+        deadline: Instant,
+    ) -> std::io::Result<RunState> {
+        let cancel = self.cancel.0.clone();
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let capture = self.output.capture(stdout, stderr, self.max_output_bytes);
+        let stdin = child.stdin.take();
+        let feed = async {
+            match &self.stdin {
+                Some(input) => input.feed(stdin).await,
+                None => Ok(()),
+            }
+        };
+        let completion = async { tokio::try_join!(capture, feed, child.wait()) };
+        // `None` if the run was cancelled
+        let outcome = tokio::select! {
+            outcome = tokio::time::timeout_at(deadline, completion) => Some(outcome),
+            () = cancel.notified() => None,
+        };
+        let ((stdout, stderr, truncated), (), status) = match outcome {
+            Some(Ok(result)) => result?,
+            Some(Err(_)) => {
+                kill_process_group(child);
+                child.wait().await?;
+                return Ok(RunState::TimedOut);
+            }
+            None => {
+                kill_process_group(child);
+                child.wait().await?;
+                return Ok(RunState::Cancelled);
+            }
+        };
+        let mut output = self.output(stdout, stderr);
+        output.truncated = truncated;
+        output.successful = status.success();
+        output.status = exit_status_text(status, &runner.manifest.exit_status);
+        if let Some(cgroup) = &self.cgroup {
+            output.out_of_memory = cgroup.oom_killed().unwrap_or_else(|e| {
+                log::warn!("failed to read the memory events of the run: {e}");
+                false
+            });
+        }
+        if output.out_of_memory {
+            output.successful = false;
+            output.status = "Out of memory".to_string();
+        }
+        if let Some(probe) = self.namespaces.take() {
+            output.namespaces = match probe.await.map_err(std::io::Error::other) {
+                Ok(Ok(namespaces)) => namespaces,
+                Ok(Err(e)) | Err(e) => {
+                    log::warn!("failed to find the namespaces of run {}: {e}", self.id);
+                    kincir_bwrap::NsFlags::empty()
+                }
+            };
+        }
+        output.trace = self.store(self.read_trace()?);
+        Ok(RunState::Complete(output))
+    }
+
+    /// Read the trace written by the sandbox, or an empty trace if it shouldn't be shown to the
+    /// user (see [`RunnerManifest::show_trace`]).
+    ///
+    /// A missing trace file is an empty trace, and only the first [`MAX_TRACE_SIZE`] bytes are
+    /// kept so that a runaway trace can't exhaust the memory of the service
+    fn read_trace(&self) -> std::io::Result<output::CapturedOutput> {
+        use nix::fcntl::OFlag;
+        use std::{io::Read, os::unix::fs::OpenOptionsExt};
+
+        let mut trace = Vec::new();
+        let Some(dir) = self.trace.as_ref().filter(|_| self.show_trace) else {
+            return Ok(output::CapturedOutput::Bytes(trace));
+        };
+        // the directory is writable by the sandbox: the file could have been replaced by a
+        // symlink to a file of the host, or by a fifo that would block forever
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags((OFlag::O_NOFOLLOW | OFlag::O_NONBLOCK).bits())
+            .open(dir.host().join(TRACE_FILE_NAME));
+        match file {
+            Ok(file) if file.metadata()?.is_file() => {
+                file.take(MAX_TRACE_SIZE).read_to_end(&mut trace)?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Ok(_) => log::warn!("the trace of run {} isn't a regular file", self.id),
+            Err(e) if e.raw_os_error() == Some(nix::errno::Errno::ELOOP as i32) => {
+                log::warn!("the trace of run {} is a symlink", self.id);
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(output::CapturedOutput::Bytes(trace))
+    }
+
+    /// Mark the run as over with the given `state`, and call the [`Runner::on_complete`]
+    /// callbacks of its runner.
+    ///
+    /// The directories of the step are removed once the callbacks are called (see
+    /// [`Run::remove_step_dirs`])
+    fn finish(&mut self, runner: &Runner, state: RunState) {
+        self.state = state;
+        self.finished_at = Some(Instant::now());
+        for callback in &runner.on_complete {
+            callback(self);
+        }
+        self.remove_step_dirs();
+    }
+
+    /// Remove the directories created on the host for the last step (trace, submitted files and
+    /// `safe-launch`).
+    ///
+    /// They are also removed when the run is dropped, but a finished run can be kept for a while
+    /// (see [`run_registry::RunRegistry`]) while its sandbox no longer uses them. The scratch
+    /// directory is kept for the next steps, until [`Run::end`]
+    fn remove_step_dirs(&mut self) {
+        self.trace = None;
+        self.submitted = None;
+        self.safe_launch = None;
+    }
+
+    /// Remove the scratch directory of the run, once no more steps will be launched.
+    ///
+    /// It is also removed when the run is dropped, this only frees the disk earlier for a run
+    /// that is kept around. A step launched afterward gets a new empty scratch directory
+    pub fn end(&mut self) {
+        self.scratch = None;
+    }
+
+    /// Create the [`RunOutput`] of the run from the captured output
+    fn output(&self, stdout: output::CapturedOutput, stderr: output::CapturedOutput) -> RunOutput {
+        RunOutput {
+            trace: output::CapturedOutput::Bytes(Vec::new()),
+            stdout: self.store(stdout),
+            stderr: self.store(stderr),
+            encoding: self.encoding,
+            truncated: false,
+            status: String::new(),
+            successful: false,
+            out_of_memory: false,
+            namespaces: kincir_bwrap::NsFlags::empty(),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// How a captured output is kept in the [`RunOutput`]: gzip-compressed if
+    /// [`RunnerManifest::compress_output`] is set, the accessors (such as
+    /// [`RunOutput::stdout_text`]) still give back the decompressed output
+    fn store(&self, captured: output::CapturedOutput) -> output::CapturedOutput {
+        #[cfg(feature = "compression")]
+        if self.compress_output {
+            return captured.compress();
+        }
+        captured
+    }
+
+    /// Create the sandbox command for a step of the run.
+    ///
+    /// What is shared between the steps (such as the scratch directory) is set up when the first
+    /// step is created and reused by the following ones.
+    fn step_command(
+        &mut self,
+        program: impl AsRef<std::ffi::OsStr>,
+    ) -> std::io::Result<kincir_bwrap::BwrapCommand<'static>> {
+        let mut command = kincir_bwrap::BwrapCommand::new(program);
+        // mounted before the scratch directory, which would be hidden if it lives inside `/tmp`
+        if let Some(size) = self.write_quota {
+            command.tmpfs_with_size("/tmp", size);
+        }
+        if let Some(guest) = self.scratch_dir.as_ref() {
+            let scratch = match self.scratch.take() {
+                Some(scratch) => scratch,
+                None => scratch::ScratchDir::create(guest)?,
+            };
+            scratch.bind(&mut command);
+            self.scratch = Some(scratch);
+        }
+        Ok(command)
+    }
+
+    /// The rlimits that will be set in the pre-exec hook of the sandbox.
+    ///
+    /// Limits that aren't configured are inherited from the service and aren't listed here
+    pub fn effective_rlimits(
+        &self,
+    ) -> Vec<(nix::sys::resource::Resource, nix::sys::resource::rlim_t)> {
+        self.rlimits().rlimits()
+    }
+
+    /// The limits of the run enforced with rlimits, without the ones enforced by its cgroup
+    /// instead (see [`Run::in_cgroup`])
+    fn rlimits(&self) -> limits::ResourceLimits {
+        let mut limits = self.limits.clone();
+        if self.cgroup.is_some() {
+            limits.memory_limit = None;
+        }
+        limits
+    }
+
+    /// Stream the stdout and stderr of the sandbox directly into the given files instead of
+    /// buffering them in memory.
+    pub fn output_to_files(
+        &mut self,
+        stdout: impl Into<PathBuf>,
+        stderr: impl Into<PathBuf>,
+    ) -> &mut Self {
+        self.output = output::OutputDestination::Files {
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+        };
+        self
+    }
+}
+
+/// The text describing how the sandbox exited: the message of the exit code in `messages` (see
+/// [`RunnerManifest::exit_status`]), or `Exit code: {code}`/`Signal: {name}` if it isn't
+/// documented
+fn exit_status_text(status: std::process::ExitStatus, messages: &HashMap<i32, String>) -> String {
+    use std::os::unix::process::ExitStatusExt;
+
+    match (status.code(), status.signal()) {
+        (Some(code), _) => match messages.get(&code) {
+            Some(message) => message.clone(),
+            None => format!("Exit code: {code}"),
+        },
+        (None, Some(signal)) => match nix::sys::signal::Signal::try_from(signal) {
+            Ok(signal) => format!("Signal: {}", signal.as_str()),
+            Err(_) => format!("Signal: {signal}"),
+        },
+        (None, None) => status.to_string(),
+    }
+}
+
+/// Kill the process group led by `child` (see [`Run::spawn`]), meaning the sandbox and every
+/// process it spawned
+fn kill_process_group(child: &tokio::process::Child) {
+    use nix::{sys::signal, unistd::Pid};
+
+    // the child was already reaped, its group can't be safely targeted anymore
+    let Some(pid) = child.id() else {
+        return;
+    };
+    let Ok(pid) = i32::try_from(pid) else {
+        return;
+    };
+    if let Err(e) = signal::killpg(Pid::from_raw(pid), signal::Signal::SIGKILL) {
+        log::warn!("failed to kill the process group {pid}: {e}");
+    }
 }
 
 /// Describe an [`Runner`], which will then be able to execute [`Run`]s.
@@ -137,9 +909,28 @@ pub struct RunnerManifest {
     #[serde(default)]
     pub files_deps: HashMap<PathBuf, PathBuf>,
 
-    /// The program that will be launched inside the sandbox (right after a simple wrapper that
-    /// will do more work inside the sandbox such as limiting the number of processes to a
-    /// reasonable limit).
+    /// The maximum total size (in bytes) of the files dependencies.
+    ///
+    /// Every file is bound into each sandbox, a large total usually means that an artifact was
+    /// included by mistake. Going over this is reported as a warning when loading the runner,
+    /// unless [`RunnerManifest::deny_large_files_deps`] is set.
+    #[serde(default)]
+    pub max_files_deps_size: Option<u64>,
+
+    /// Fail to load the runner (instead of only warning) when the files dependencies are bigger
+    /// than [`RunnerManifest::max_files_deps_size`]
+    #[serde(default)]
+    pub deny_large_files_deps: bool,
+
+    /// The maximum total size (in bytes) of the files submitted for a single [`Run`] (see
+    /// [`RunRequest`]). Launching a run with larger files fails.
+    /// This defaults to 16MiB if not present
+    #[serde(default = "RunnerManifest::default_max_submitted_size_value")]
+    pub max_submitted_size: u64,
+
+    /// The program that will be launched inside the sandbox. The resources limits (such as the
+    /// number of processes, see [`RunnerManifest::limits`]) are set right before the sandbox is
+    /// started, meaning that they apply to the entry and everything it launches.
     /// it will be given some envirment variable:
     /// - `FILES_ROOT`: the directory which will contain every requested files in the manifest
     ///
@@ -149,17 +940,97 @@ pub struct RunnerManifest {
     ///
     /// - `TRACE_FILE`: Where you should log stuff if you want to give a trace
     ///
+    /// Other variables can be added with [`RunnerManifest::env`].
+    ///
     /// For security, do note that these should NOT be passed down to the tested program.
-    /// a utility named `safe-launch` can be used to call the program with these variable
-    /// sanitized.
+    /// a utility named `safe-launch` (available at `/bin/safe-launch`) can be used to call the
+    /// program with these variable sanitized, for example `safe-launch ./a.out arg1 arg2`. It
+    /// needs `bash`, which is one of the default binaries.
     pub entry: PathBuf,
 
+    /// Extra environment variables given to the entry (for example `LANG` or the configuration
+    /// of a tool).
+    ///
+    /// It is an error to set one of the variables reserved by the runner (see
+    /// [`RunnerManifest::RESERVED_ENV`]). Do note that `safe-launch` only removes the reserved
+    /// variables: these are passed down to the tested program.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// The program used to launch the entry (for example `bash` or `python3`).
+    ///
+    /// If set, the entry will be given as the first argument to the interpreter, meaning that it
+    /// doesn't need to be executable.
+    ///
+    /// This is the name of a binary (not a path), which is added to the binary dependencies if it
+    /// isn't one of them already.
+    #[serde(default)]
+    pub interpreter: Option<String>,
+
+    /// Where a writable scratch directory will be mounted inside of the sandbox.
+    ///
+    /// The directory is empty at the start of each [`Run`], and persists between the steps of a
+    /// single [`Run`] (for example to keep the artifacts of a compilation step for the execution
+    /// step).
+    #[serde(default)]
+    pub scratch_dir: Option<PathBuf>,
+
+    /// How the captured stdout/stderr are converted to text when the output contains invalid
+    /// UTF-8 (`lossy` or `escape`).
+    ///
+    /// The raw bytes are always kept, this defaults to `lossy`
+    #[serde(default)]
+    pub output_encoding: output::OutputEncoding,
+
+    /// The maximum number of bytes kept from each of the stdout and stderr of the sandbox when
+    /// they are captured in memory, the rest is discarded and the output is marked as truncated.
+    /// This defaults to 1MiB if not present
+    #[serde(default = "RunnerManifest::default_max_output_bytes_value")]
+    pub max_output_bytes: u64,
+
+    /// Store the captured trace, stdout and stderr gzip-compressed in the [`RunOutput`], for
+    /// runners with a large output that is kept around. This defaults to `false`
+    #[cfg(feature = "compression")]
+    #[serde(default)]
+    pub compress_output: bool,
+
+    /// The maximum number of bytes that the sandbox can write, in its writable `/tmp`.
+    ///
+    /// `/tmp` is a tmpfs capped to this size, meaning that writes past it fail with `ENOSPC`
+    /// instead of filling the host's disk (or memory).
+    /// The scratch directory (see [`RunnerManifest::scratch_dir`]) lives on the host and can't be
+    /// limited, so a runner can't have both (see [`RunnerManifest::verify_write_quota`]).
+    #[serde(default)]
+    pub write_quota: Option<usize>,
+
+    /// Give the sandbox access to the network of the host. This defaults to false, meaning that
+    /// the sandbox lives in its own network namespace with only a loopback interface.
+    ///
+    /// # Security
+    ///
+    /// With the network, the untrusted submitted code can reach anything the host can: the
+    /// services listening on the host's loopback (databases, admin interfaces...), the internal
+    /// network, and the internet (to download more code or exfiltrate the files of the runner).
+    /// Only enable this for runners that truly need it, and firewall the host accordingly.
+    ///
+    /// Do also note that name resolution needs files such as `/etc/resolv.conf`, which aren't
+    /// bound into the sandbox by default
+    #[serde(default)]
+    pub network: bool,
+
     /// The time after which the sandbox (and every processes inside) will be killed.
     /// This defaults to 10s if not present
     #[serde(default = "RunnerManifest::default_timeout_value")]
     #[serde_as(as = "serde_with::DurationSeconds<u64, serde_with::formats::Flexible>")]
     pub timeout: Duration,
 
+    /// The time after which each lookup done while loading the runner (such as searching a
+    /// binary dependency in the `PATH`) is considered stuck, failing the loading of the runner.
+    /// This defaults to 5s if not present
+    #[serde(default = "RunnerManifest::default_setup_timeout_value")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64, serde_with::formats::Flexible>")]
+    pub setup_timeout: Duration,
+
     /// Do not include default binaries into the $PATH
     /// by default this is false, meaning that if you do not specify a value it WILL include the
     /// default binaries
@@ -187,9 +1058,35 @@ pub struct RunnerManifest {
     ///  - 4: Using illegal functions
     #[serde(default)]
     pub exit_status: HashMap<i32, String>,
+
+    /// The resources limits applied to the sandbox.
+    ///
+    /// See [`limits::ResourceLimits`] for the list of keys (they are written directly in the
+    /// manifest)
+    #[serde(flatten)]
+    pub limits: limits::ResourceLimits,
+
+    /// The name of a resource profile to take the limits from.
+    ///
+    /// The limits explicitly set in the manifest take precedence over the ones of the profile.
+    /// It is an error to use a profile that doesn't exist
+    #[serde(default)]
+    pub resource_profile: Option<String>,
+
+    /// The directory holding the directory of this runner (`./runners` by default), the files
+    /// dependencies are looked up in `<runners_dir>/<name>/`.
+    ///
+    /// This isn't part of the manifest, it is set when the runner is loaded (see
+    /// [`loader::load_runners`])
+    #[serde(skip, default = "RunnerManifest::default_runners_dir_value")]
+    pub runners_dir: PathBuf,
 }
 
 impl RunnerManifest {
+    /// The environment variables set by the runner itself, which can't be set in
+    /// [`RunnerManifest::env`]
+    pub const RESERVED_ENV: &[&str] = &["FILES_ROOT", "PATH", "SUBMITTED_ROOT", "TRACE_FILE"];
+
     /// All the binaries that will be installed by default onto the sandbox
     pub const DEFAULT_COMMANDS: &[&str] = &[
         "[",
@@ -301,31 +1198,109 @@ impl RunnerManifest {
         "yes",
     ];
 
+    /// The namespace flag handling the network of the sandbox, see [`RunnerManifest::network`]
+    fn network_flag(&self) -> kincir_bwrap::NsFlags {
+        if self.network {
+            kincir_bwrap::NsFlags::SHARE_NET
+        } else {
+            kincir_bwrap::NsFlags::NET
+        }
+    }
+
     /// The default timeout value. Used by serde if the value is not specified in the manifest
     fn default_timeout_value() -> Duration {
         Duration::from_secs(10)
     }
 
+    /// The default maximum size of the captured outputs. Used by serde if the value is not
+    /// specified in the manifest
+    fn default_max_output_bytes_value() -> u64 {
+        1 << 20
+    }
+
+    /// The default maximum size of the submitted files. Used by serde if the value is not
+    /// specified in the manifest
+    fn default_max_submitted_size_value() -> u64 {
+        16 << 20
+    }
+
+    /// The default directory holding the runners. Used by serde since it isn't part of the
+    /// manifest
+    fn default_runners_dir_value() -> PathBuf {
+        PathBuf::from("./runners")
+    }
+
+    /// Where a path of the files dependencies is located on the host: next to the manifest.yml,
+    /// even if the path is absolute
+    fn host_path(&self, path: &Path) -> PathBuf {
+        let mut host_real_path = self.runners_dir.join(&self.name).into_os_string();
+        host_real_path.push("/");
+        host_real_path.push(path);
+        PathBuf::from(host_real_path)
+    }
+
+    /// The default setup timeout value. Used by serde if the value is not specified in the
+    /// manifest
+    fn default_setup_timeout_value() -> Duration {
+        Duration::from_secs(5)
+    }
+
     pub fn verify_bin_deps(&self) -> Result<HashMap<String, PathBuf>, RunnerBinaryDepError<'_>> {
-        let mut output = HashMap::with_capacity(self.bin_deps.len());
-        for bin in &self.bin_deps {
-            if output
-                .insert(
-                    bin.clone(),
-                    which::which(bin).map_err(|e| RunnerBinaryDepError::WhichError(bin, e))?,
-                )
-                .is_some()
-            {
-                return Err(RunnerBinaryDepError::Duplicate(bin));
+        self.verify_bin_deps_with(|bin| which::which(bin))
+    }
+
+    /// Same as [`RunnerManifest::verify_bin_deps`], but the binaries are searched using
+    /// `resolver`.
+    ///
+    /// The binaries are searched one after the other on a single thread, each search being
+    /// bounded by [`RunnerManifest::setup_timeout`], so that a stuck filesystem (such as a
+    /// stalled NFS mount) produces an error instead of blocking forever
+    pub fn verify_bin_deps_with<R>(
+        &self,
+        resolver: R,
+    ) -> Result<HashMap<String, PathBuf>, RunnerBinaryDepError<'_>>
+    where
+        R: Fn(&str) -> which::Result<PathBuf> + Send + 'static,
+    {
+        if let Some(bin) = self.bin_deps.iter().duplicates().next() {
+            return Err(RunnerBinaryDepError::Duplicate(bin));
+        }
+        // launched as `/bin/<interpreter>`
+        if let Some(interpreter) = self
+            .interpreter
+            .as_deref()
+            .filter(|i| i.is_empty() || i.contains('/'))
+        {
+            return Err(RunnerBinaryDepError::InvalidInterpreter(interpreter));
+        }
+        let mut bins = self.bin_deps.iter().map(String::as_str).collect::<Vec<_>>();
+        for &bin in Self::DEFAULT_COMMANDS
+            .iter()
+            .chain(&self.interpreter.as_deref())
+        {
+            if !bins.contains(&bin) {
+                bins.push(bin);
             }
         }
-        for &bin in Self::DEFAULT_COMMANDS {
-            if !output.contains_key(bin) {
-                output.insert(
-                    bin.to_string(),
-                    which::which(bin).map_err(|e| RunnerBinaryDepError::WhichError(bin, e))?,
-                );
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let owned = bins.iter().map(ToString::to_string).collect::<Vec<_>>();
+        // a search that timed out is left running in the background (a thread blocked on a
+        // filesystem can't be killed), the following ones are then skipped
+        std::thread::spawn(move || {
+            for bin in owned {
+                if sender.send(resolver(&bin)).is_err() {
+                    break;
+                }
             }
+        });
+        let mut output = HashMap::with_capacity(bins.len());
+        for bin in bins {
+            let path = receiver
+                .recv_timeout(self.setup_timeout)
+                .map_err(|_| RunnerBinaryDepError::Timeout(bin, self.setup_timeout))?
+                .map_err(|e| RunnerBinaryDepError::WhichError(bin, e))?;
+            output.insert(bin.to_string(), path);
         }
         Ok(output)
     }
@@ -342,9 +1317,7 @@ impl RunnerManifest {
             return Err(RunnerFilesDepError::Duplicates(duplicates));
         }
         for (host_path, guest_path) in &self.files_deps {
-            let mut host_real_path = std::ffi::OsString::from(format!("./runners/{}/", self.name));
-            host_real_path.push(host_path);
-            let host_real_path = PathBuf::from(host_real_path);
+            let host_real_path = self.host_path(host_path);
             if !host_real_path.exists() {
                 return Err(RunnerFilesDepError::Missing(host_path.as_path()));
             }
@@ -356,10 +1329,13 @@ impl RunnerManifest {
             }) {
                 return Err(RunnerFilesDepError::InvalidPath(host_path.as_path()));
             }
+            // the guest path is joined to the files root, an absolute path would replace it
             if guest_path.components().any(|s| {
                 matches!(
                     s,
-                    std::path::Component::ParentDir | std::path::Component::Prefix(_)
+                    std::path::Component::ParentDir
+                        | std::path::Component::Prefix(_)
+                        | std::path::Component::RootDir
                 )
             }) {
                 return Err(RunnerFilesDepError::InvalidPath(guest_path.as_path()));
@@ -368,12 +1344,168 @@ impl RunnerManifest {
         }
         Ok(out)
     }
+
+    /// Check that the total size of the files dependencies is below
+    /// [`RunnerManifest::max_files_deps_size`].
+    ///
+    /// Directories are counted with their whole content, and the missing files are ignored (they
+    /// are reported by [`RunnerManifest::verify_files_deps`])
+    pub fn verify_files_deps_size(&self) -> Result<(), RunnerFilesDepError<'_>> {
+        let Some(max) = self.max_files_deps_size else {
+            return Ok(());
+        };
+        let mut size = 0;
+        for host_path in self.files_deps.keys() {
+            size += disk_size(&self.host_path(host_path)).unwrap_or(0);
+        }
+        if size > max {
+            return Err(RunnerFilesDepError::TooLarge { size, max });
+        }
+        Ok(())
+    }
+
+    /// The load-time check of the files dependencies size: an error if
+    /// [`RunnerManifest::deny_large_files_deps`] is set, otherwise only a warning
+    pub fn check_files_deps_size(&self) -> Result<(), RunnerFilesDepError<'_>> {
+        match self.verify_files_deps_size() {
+            Err(e @ RunnerFilesDepError::TooLarge { .. }) if !self.deny_large_files_deps => {
+                log::warn!("runner `{}`: {e}", self.name);
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// Check that the exit status table doesn't document the exit code 0, which is always a
+    /// successful run
+    pub fn verify_exit_status(&self) -> Result<(), RunnerExitStatusError<'_>> {
+        match self.exit_status.get(&0) {
+            Some(message) => Err(RunnerExitStatusError::SuccessCode(message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that the extra environment variables have valid names, and don't override one of
+    /// the [`RunnerManifest::RESERVED_ENV`]
+    pub fn verify_env(&self) -> Result<(), RunnerEnvError<'_>> {
+        for name in self.env.keys().sorted() {
+            if Self::RESERVED_ENV.contains(&name.as_str()) {
+                return Err(RunnerEnvError::Reserved(name));
+            }
+            if name.is_empty() || name.contains(['=', '\0']) {
+                return Err(RunnerEnvError::InvalidName(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the writable space of the sandbox is bounded when a
+    /// [`RunnerManifest::write_quota`] is given, meaning that there is no scratch directory
+    /// (which lives on the host's disk, and persists between the steps of a run)
+    pub fn verify_write_quota(&self) -> Result<(), RunnerWriteQuotaError<'_>> {
+        match (self.write_quota, &self.scratch_dir) {
+            (Some(_), Some(scratch_dir)) => Err(RunnerWriteQuotaError::WithScratchDir(scratch_dir)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the entry can be launched, meaning that it is a path inside of the files root,
+    /// and that it is either executable or that an interpreter is configured.
+    ///
+    /// The executable bit is only checked if the entry is one of the files dependencies, and
+    /// isn't fatal: [`RunnerEntryError::NotExecutable`] should be reported as a warning since the
+    /// runner could still work (for example if the entry is created at runtime)
+    pub fn verify_entry(&self) -> Result<(), RunnerEntryError<'_>> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if self.entry.components().any(|s| {
+            matches!(
+                s,
+                std::path::Component::ParentDir
+                    | std::path::Component::Prefix(_)
+                    | std::path::Component::RootDir
+            )
+        }) {
+            return Err(RunnerEntryError::InvalidPath(self.entry.as_path()));
+        }
+        if self.interpreter.is_some() {
+            return Ok(());
+        }
+        let Some(host_path) = self
+            .files_deps
+            .iter()
+            .find_map(|(host, guest)| (guest == &self.entry).then_some(host))
+        else {
+            return Ok(());
+        };
+        match std::fs::metadata(self.host_path(host_path)) {
+            Ok(metadata) if metadata.permissions().mode() & 0o111 == 0 => {
+                Err(RunnerEntryError::NotExecutable(self.entry.as_path()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Run `f` on another thread, giving up on it after `timeout`.
+///
+/// A call that timed out is left running in the background (a thread blocked on a filesystem
+/// can't be killed), only its result is discarded
+fn with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Option<T> {
+    let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+    std::thread::spawn(move || {
+        // the receiver is gone if we timed out, there is nobody to give the result to
+        let _ = sender.send(f());
+    });
+    receiver.recv_timeout(timeout).ok()
+}
+
+/// The size of the file at `path`, or of every file inside of it if it is a directory.
+///
+/// The symlinks aren't followed
+fn disk_size(path: &Path) -> std::io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += disk_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[derive(Debug)]
+pub enum RunnerEntryError<'a> {
+    NotExecutable(&'a Path),
+    InvalidPath(&'a Path),
+}
+
+#[derive(Debug)]
+pub enum RunnerEnvError<'a> {
+    Reserved(&'a str),
+    InvalidName(&'a str),
+}
+
+#[derive(Debug)]
+pub enum RunnerExitStatusError<'a> {
+    SuccessCode(&'a str),
+}
+
+#[derive(Debug)]
+pub enum RunnerWriteQuotaError<'a> {
+    WithScratchDir(&'a Path),
 }
 
 #[derive(Debug)]
 pub enum RunnerBinaryDepError<'a> {
     Duplicate(&'a str),
     WhichError(&'a str, which::Error),
+    Timeout(&'a str, Duration),
+    InvalidInterpreter(&'a str),
 }
 
 #[derive(Debug)]
@@ -381,6 +1513,7 @@ pub enum RunnerFilesDepError<'a> {
     Duplicates(Vec<&'a Path>),
     Missing(&'a Path),
     InvalidPath(&'a Path),
+    TooLarge { size: u64, max: u64 },
 }
 
 impl<'a> std::fmt::Display for RunnerBinaryDepError<'a> {
@@ -388,12 +1521,77 @@ impl<'a> std::fmt::Display for RunnerBinaryDepError<'a> {
         match self {
             Self::Duplicate(bin) => writeln!(f, "duplicate binary dependency for: `{bin}`"),
             Self::WhichError(bin, e) => writeln!(f, "which error for dependency `{bin}`: {e}"),
+            Self::Timeout(bin, timeout) => writeln!(
+                f,
+                "searching dependency `{bin}` took more than {}s",
+                timeout.as_secs_f64()
+            ),
+            Self::InvalidInterpreter(interpreter) => writeln!(
+                f,
+                "the interpreter must be the name of a binary, not `{interpreter}`"
+            ),
         }
     }
 }
 
 impl<'a> std::error::Error for RunnerBinaryDepError<'a> {}
 
+impl<'a> std::fmt::Display for RunnerEntryError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotExecutable(p) => writeln!(
+                f,
+                "entry {} isn't executable and no interpreter is set",
+                p.display()
+            ),
+            Self::InvalidPath(p) => writeln!(f, "invalid entry path {}", p.display()),
+        }
+    }
+}
+
+impl<'a> std::error::Error for RunnerEntryError<'a> {}
+
+impl<'a> std::fmt::Display for RunnerEnvError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reserved(name) => writeln!(
+                f,
+                "environment variable `{name}` is reserved and can't be set"
+            ),
+            Self::InvalidName(name) => writeln!(f, "invalid environment variable name `{name}`"),
+        }
+    }
+}
+
+impl<'a> std::error::Error for RunnerEnvError<'a> {}
+
+impl<'a> std::fmt::Display for RunnerExitStatusError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SuccessCode(message) => writeln!(
+                f,
+                "exit code 0 is always a success and can't have a message (`{message}`)"
+            ),
+        }
+    }
+}
+
+impl<'a> std::error::Error for RunnerExitStatusError<'a> {}
+
+impl<'a> std::fmt::Display for RunnerWriteQuotaError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WithScratchDir(path) => writeln!(
+                f,
+                "the scratch directory {} can't be limited by the write quota",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl<'a> std::error::Error for RunnerWriteQuotaError<'a> {}
+
 impl<'a> std::fmt::Display for RunnerFilesDepError<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -406,6 +1604,953 @@ impl<'a> std::fmt::Display for RunnerFilesDepError<'a> {
             }
             RunnerFilesDepError::Missing(p) => writeln!(f, "missing path: {}", p.display()),
             RunnerFilesDepError::InvalidPath(p) => writeln!(f, "invalid path {}", p.display()),
+            RunnerFilesDepError::TooLarge { size, max } => writeln!(
+                f,
+                "files dependencies are too large: {size} bytes (maximum is {max} bytes)"
+            ),
+        }
+    }
+}
+
+impl<'a> std::error::Error for RunnerFilesDepError<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Runner {
+        /// The sandbox command launching the entry for the given `run`
+        fn entry_command(
+            &self,
+            run: &mut Run,
+            request: &RunRequest,
+        ) -> std::io::Result<kincir_bwrap::BwrapCommand<'static>> {
+            let (program, args) = self.entry(run);
+            self.launch_command(run, request, program, &args)
+        }
+    }
+    use std::ffi::OsStr;
+
+    pub(super) fn manifest(name: &str) -> RunnerManifest {
+        RunnerManifest {
+            show_trace: false,
+            name: name.to_string(),
+            bin_deps: Vec::new(),
+            files_deps: HashMap::new(),
+            max_files_deps_size: None,
+            deny_large_files_deps: false,
+            max_submitted_size: RunnerManifest::default_max_submitted_size_value(),
+            entry: PathBuf::from("entry.sh"),
+            env: HashMap::new(),
+            interpreter: None,
+            scratch_dir: None,
+            output_encoding: output::OutputEncoding::default(),
+            max_output_bytes: RunnerManifest::default_max_output_bytes_value(),
+            #[cfg(feature = "compression")]
+            compress_output: false,
+            write_quota: None,
+            network: false,
+            timeout: RunnerManifest::default_timeout_value(),
+            setup_timeout: RunnerManifest::default_setup_timeout_value(),
+            no_default_binary: false,
+            exit_status: HashMap::new(),
+            limits: limits::ResourceLimits::default(),
+            resource_profile: None,
+            runners_dir: RunnerManifest::default_runners_dir_value(),
+        }
+    }
+
+    pub(super) fn runner(manifest: RunnerManifest) -> Runner {
+        Runner {
+            id: uuid::Uuid::new_v4(),
+            limits: manifest.limits.clone(),
+            manifest,
+            bin_deps: HashMap::new(),
+            file_deps: HashMap::new(),
+            absolute_file_deps: HashSet::new(),
+            on_complete: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn entry_not_executable() {
+        // removed when dropped, even if an assertion fails
+        let runners = scratch::ScratchDir::create("/runners").unwrap();
+        let dir = runners.host().join("entry");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("entry.sh"), "echo hello").unwrap();
+
+        let mut manifest = manifest("entry");
+        manifest.runners_dir = runners.host().to_path_buf();
+        manifest
+            .files_deps
+            .insert(PathBuf::from("entry.sh"), PathBuf::from("entry.sh"));
+        let result = manifest.verify_entry();
+        assert!(matches!(result, Err(RunnerEntryError::NotExecutable(_))));
+
+        manifest.interpreter = Some("sh".to_string());
+        assert!(manifest.verify_entry().is_ok());
+    }
+
+    #[test]
+    fn paths_outside_files_root() {
+        let mut manifest = manifest("outside");
+        manifest.entry = PathBuf::from("/bin/sh");
+        assert!(matches!(
+            manifest.verify_entry(),
+            Err(RunnerEntryError::InvalidPath(p)) if p == Path::new("/bin/sh")
+        ));
+        manifest.entry = PathBuf::from("../entry.sh");
+        assert!(matches!(
+            manifest.verify_entry(),
+            Err(RunnerEntryError::InvalidPath(_))
+        ));
+
+        let runners = scratch::ScratchDir::create("/runners").unwrap();
+        std::fs::create_dir_all(runners.host().join("outside")).unwrap();
+        std::fs::write(runners.host().join("outside/config"), "").unwrap();
+        manifest.runners_dir = runners.host().to_path_buf();
+        manifest
+            .files_deps
+            .insert(PathBuf::from("config"), PathBuf::from("/etc/config"));
+        assert!(matches!(
+            manifest.verify_files_deps(),
+            Err(RunnerFilesDepError::InvalidPath(p)) if p == Path::new("/etc/config")
+        ));
+    }
+
+    #[test]
+    fn interpreter_bin_dep() {
+        let resolver = |bin: &str| Ok(PathBuf::from("/usr/bin").join(bin));
+        let mut manifest = manifest("interpreter");
+        manifest.interpreter = Some("python3".to_string());
+        let bin_deps = manifest.verify_bin_deps_with(resolver).unwrap();
+        assert_eq!(bin_deps["python3"], Path::new("/usr/bin/python3"));
+
+        manifest.interpreter = Some("/usr/bin/python3".to_string());
+        assert!(matches!(
+            manifest.verify_bin_deps_with(resolver),
+            Err(RunnerBinaryDepError::InvalidInterpreter("/usr/bin/python3"))
+        ));
+    }
+
+    #[test]
+    fn bin_deps_timeout() {
+        let mut manifest = manifest("timeout");
+        manifest.bin_deps.push("slow".to_string());
+        manifest.setup_timeout = Duration::from_millis(50);
+        let result = manifest.verify_bin_deps_with(|bin| {
+            std::thread::sleep(Duration::from_secs(1));
+            Ok(PathBuf::from("/bin").join(bin))
+        });
+        assert!(matches!(
+            result,
+            Err(RunnerBinaryDepError::Timeout("slow", _))
+        ));
+    }
+
+    #[test]
+    fn bin_deps_single_thread() {
+        let threads = std::sync::Arc::new(std::sync::Mutex::new(HashSet::new()));
+        let mut manifest = manifest("threads");
+        manifest.bin_deps = vec!["gcc".to_string(), "ls".to_string()];
+        manifest.interpreter = Some("python3".to_string());
+        let seen = threads.clone();
+        let bin_deps = manifest
+            .verify_bin_deps_with(move |bin| {
+                seen.lock().unwrap().insert(std::thread::current().id());
+                Ok(PathBuf::from("/usr/bin").join(bin))
+            })
+            .unwrap();
+        assert_eq!(threads.lock().unwrap().len(), 1);
+        assert_eq!(bin_deps["gcc"], Path::new("/usr/bin/gcc"));
+        assert_eq!(bin_deps["python3"], Path::new("/usr/bin/python3"));
+        for bin in RunnerManifest::DEFAULT_COMMANDS {
+            assert!(bin_deps.contains_key(*bin));
         }
+
+        manifest.bin_deps.push("gcc".to_string());
+        assert!(matches!(
+            manifest.verify_bin_deps_with(|bin| Ok(PathBuf::from(bin))),
+            Err(RunnerBinaryDepError::Duplicate("gcc"))
+        ));
+    }
+
+    #[test]
+    fn large_files_deps() {
+        // removed when dropped, even if an assertion fails
+        let runners = scratch::ScratchDir::create("/runners").unwrap();
+        let dir = runners.host().join("large");
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(dir.join("entry.sh"), [0; 16]).unwrap();
+        std::fs::write(dir.join("data/artifact"), [0; 1024]).unwrap();
+
+        let mut manifest = manifest("large");
+        manifest.runners_dir = runners.host().to_path_buf();
+        manifest
+            .files_deps
+            .insert(PathBuf::from("entry.sh"), PathBuf::from("entry.sh"));
+        manifest
+            .files_deps
+            .insert(PathBuf::from("data"), PathBuf::from("data"));
+        manifest.max_files_deps_size = Some(1024);
+        assert!(matches!(
+            manifest.verify_files_deps_size(),
+            Err(RunnerFilesDepError::TooLarge {
+                size: 1040,
+                max: 1024
+            })
+        ));
+        // only a warning by default
+        assert!(manifest.check_files_deps_size().is_ok());
+        manifest.deny_large_files_deps = true;
+        assert!(manifest.check_files_deps_size().is_err());
+        manifest.max_files_deps_size = Some(2048);
+        assert!(manifest.verify_files_deps_size().is_ok());
+    }
+
+    #[test]
+    fn effective_rlimits() {
+        use nix::sys::resource::Resource;
+
+        let mut manifest = manifest("rlimits");
+        manifest.limits.max_threads = Some(64);
+        manifest.limits.max_open_files = Some(128);
+        let run = Run::new(&runner(manifest));
+        let rlimits = run.effective_rlimits();
+        assert!(rlimits.contains(&(Resource::RLIMIT_NPROC, 64)));
+        assert!(rlimits.contains(&(Resource::RLIMIT_NOFILE, 128)));
+    }
+
+    #[test]
+    fn effective_rlimits_in_cgroup() {
+        use nix::sys::resource::Resource;
+
+        let mut manifest = manifest("rlimits_cgroup");
+        manifest.limits.memory_limit = Some(64 << 20);
+        let runner = runner(manifest);
+        let mut run = Run::new(&runner);
+        assert!(run
+            .effective_rlimits()
+            .contains(&(Resource::RLIMIT_AS, 64 << 20)));
+
+        // the memory is limited by `memory.max` instead
+        let parent = std::env::temp_dir().join(format!("kincir-cgroup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&parent).unwrap();
+        run.in_cgroup(&parent).unwrap();
+        let rlimits = run.effective_rlimits();
+        drop(run);
+        std::fs::remove_dir_all(&parent).unwrap();
+        assert!(rlimits
+            .iter()
+            .all(|(resource, _)| *resource != Resource::RLIMIT_AS));
+        assert!(rlimits.contains(&(Resource::RLIMIT_NPROC, 256)));
+    }
+
+    #[test]
+    fn metadata() {
+        let mut run = Run::new(&runner(manifest("metadata")));
+        run.with_metadata("submission", "42")
+            .with_metadata("course", "rust-101");
+        let output = run.output(
+            output::CapturedOutput::Bytes(Vec::new()),
+            output::CapturedOutput::Bytes(Vec::new()),
+        );
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["metadata"]["submission"], "42");
+        assert_eq!(json["metadata"]["course"], "rust-101");
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compress_output() {
+        let stdout = "hello\n".repeat(1000);
+        let mut manifest = manifest("compress");
+        manifest.compress_output = true;
+        let run = Run::new(&runner(manifest));
+        let output = run.output(
+            output::CapturedOutput::Bytes(stdout.clone().into_bytes()),
+            output::CapturedOutput::Bytes(Vec::new()),
+        );
+        assert!(matches!(output.stdout, output::CapturedOutput::Gzip(_)));
+        assert_eq!(output.stdout_text().unwrap(), stdout);
+        assert_eq!(output.stderr_text().unwrap(), "");
+
+        let run = Run::new(&runner(self::manifest("uncompressed")));
+        let output = run.output(
+            output::CapturedOutput::Bytes(stdout.clone().into_bytes()),
+            output::CapturedOutput::Bytes(Vec::new()),
+        );
+        assert!(matches!(output.stdout, output::CapturedOutput::Bytes(_)));
+        assert_eq!(output.stdout_text().unwrap(), stdout);
+    }
+
+    #[test]
+    fn write_quota() {
+        let mut manifest = manifest("quota");
+        manifest.write_quota = Some(1 << 20);
+        let mut run = Run::new(&runner(manifest));
+        let args = run.step_command("dd").unwrap().build_args().unwrap();
+        assert_eq!(args[..4], ["--size", "1048576", "--tmpfs", "/tmp"]);
+    }
+
+    #[test]
+    fn write_quota_with_scratch_dir() {
+        let mut manifest = manifest("quota_scratch");
+        manifest.write_quota = Some(1 << 20);
+        assert!(manifest.verify_write_quota().is_ok());
+        manifest.scratch_dir = Some(PathBuf::from("/scratch"));
+        assert!(matches!(
+            manifest.verify_write_quota(),
+            Err(RunnerWriteQuotaError::WithScratchDir(path)) if path == Path::new("/scratch")
+        ));
+        manifest.write_quota = None;
+        assert!(manifest.verify_write_quota().is_ok());
+    }
+
+    #[test]
+    #[ignore = "requires bwrap"]
+    fn write_quota_enospc() {
+        let mut manifest = manifest("quota");
+        manifest.write_quota = Some(1 << 20);
+        let mut run = Run::new(&runner(manifest));
+        let output = run
+            .step_command("dd")
+            .unwrap()
+            .bind_read_only("/usr", "/usr")
+            .symlink("usr/bin", "/bin")
+            .symlink("usr/lib", "/lib")
+            .symlink("usr/lib64", "/lib64")
+            .dev_dir("/dev")
+            .arg("if=/dev/zero")
+            .arg("of=/tmp/file")
+            .arg("bs=1M")
+            .arg("count=4")
+            .command()
+            .unwrap()
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("No space left on device"));
+    }
+
+    #[test]
+    fn symlinked_bin_deps() {
+        let dir = std::env::temp_dir().join(format!("kincir-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        std::fs::write(dir.join("gcc"), "").unwrap();
+        std::os::unix::fs::symlink("gcc", dir.join("cc")).unwrap();
+
+        let mut runner = runner(manifest("symlink"));
+        runner.bin_deps.insert("cc".to_string(), dir.join("cc"));
+        runner.bin_deps.insert("gcc".to_string(), dir.join("gcc"));
+        let mut command = kincir_bwrap::BwrapCommand::new("cc");
+        let result = runner.bind_bin_deps(&mut command);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+
+        let args = command.build_args().unwrap();
+        let gcc = dir.join("gcc");
+        let gcc = gcc.as_os_str();
+        let symlink = [OsStr::new("--symlink"), gcc, OsStr::new("/bin/cc")];
+        assert!(args.windows(2).any(|w| w == [gcc, gcc]));
+        assert!(args.windows(3).any(|w| w == symlink));
+        assert!(args.windows(2).any(|w| w == [gcc, OsStr::new("/bin/gcc")]));
+    }
+
+    #[test]
+    fn on_complete() {
+        let finished = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut runner = runner(manifest("callback"));
+        let sink = finished.clone();
+        runner.on_complete(move |run| {
+            let successful = match &run.state {
+                RunState::Complete(output) => Some(output.successful),
+                _ => None,
+            };
+            sink.lock().unwrap().push((run.id, successful));
+        });
+
+        let mut run = Run::new(&runner);
+        let mut output = run.output(
+            output::CapturedOutput::Bytes(Vec::new()),
+            output::CapturedOutput::Bytes(Vec::new()),
+        );
+        output.successful = true;
+        run.finish(&runner, RunState::Complete(output));
+        let mut timed_out = Run::new(&runner);
+        timed_out.finish(&runner, RunState::TimedOut);
+
+        assert_eq!(
+            *finished.lock().unwrap(),
+            [(run.id, Some(true)), (timed_out.id, None)]
+        );
+    }
+
+    #[test]
+    fn launch_command() {
+        let mut manifest = manifest("launch");
+        manifest.interpreter = Some("sh".to_string());
+        let mut runner = runner(manifest);
+        runner
+            .bin_deps
+            .insert("sh".to_string(), PathBuf::from("/bin/sh"));
+        runner.file_deps.insert(
+            PathBuf::from("./runners/launch/entry.sh"),
+            PathBuf::from("entry.sh"),
+        );
+        runner.absolute_file_deps.insert(PathBuf::from("/usr/lib"));
+
+        let mut request = RunRequest::new();
+        request.with_file("main.c", "int main() {}");
+        let mut run = Run::new(&runner);
+        let args = runner
+            .entry_command(&mut run, &request)
+            .unwrap()
+            .build_args()
+            .unwrap();
+        let trace = run.trace.as_ref().unwrap().host().to_path_buf();
+        assert!(trace.join("trace").exists());
+
+        let has = |expected: &[&str]| args.windows(expected.len()).any(|w| w == expected);
+        let files_root = run.files_root.to_str().unwrap();
+        assert!(files_root.starts_with("/files/"));
+        assert!(has(&["--setenv", "FILES_ROOT", files_root]));
+        let entry = format!("{files_root}/entry.sh");
+        assert!(has(&["--setenv", "SUBMITTED_ROOT", "/submitted"]));
+        assert!(has(&["--setenv", "TRACE_FILE", "/trace/trace"]));
+        assert!(has(&["--setenv", "PATH", "/bin"]));
+        assert!(has(&["--ro-bind", "/usr/lib", "/usr/lib"]));
+        assert!(has(&["--ro-bind", "./runners/launch/entry.sh", &entry]));
+        let submitted = run.submitted.as_ref().unwrap().host().to_path_buf();
+        assert!(submitted.join("main.c").exists());
+        assert!(args.windows(3).any(|w| w
+            == [
+                OsStr::new("--ro-bind"),
+                submitted.as_os_str(),
+                OsStr::new("/submitted")
+            ]));
+        assert!(has(&["--", "/bin/sh", &entry]));
+        let safe_launch = run.safe_launch.as_ref().unwrap().host().join("safe-launch");
+        assert!(args.windows(3).any(|w| w
+            == [
+                OsStr::new("--ro-bind"),
+                safe_launch.as_os_str(),
+                OsStr::new("/bin/safe-launch")
+            ]));
+        assert!(args.windows(3).any(|w| w
+            == [
+                OsStr::new("--bind"),
+                trace.as_os_str(),
+                OsStr::new("/trace")
+            ]));
+
+        drop(run);
+        assert!(!trace.exists());
+        assert!(!submitted.exists());
+    }
+
+    #[test]
+    fn env() {
+        let args = |manifest: RunnerManifest| {
+            let runner = runner(manifest);
+            let mut run = Run::new(&runner);
+            runner
+                .entry_command(&mut run, &RunRequest::new())
+                .unwrap()
+                .build_args()
+                .unwrap()
+        };
+
+        let mut custom = manifest("env");
+        custom.env.insert("LANG".to_string(), "C.UTF-8".to_string());
+        assert!(custom.verify_env().is_ok());
+        assert!(args(custom)
+            .windows(3)
+            .any(|w| w == ["--setenv", "LANG", "C.UTF-8"]));
+
+        let mut reserved = manifest("env");
+        reserved
+            .env
+            .insert("PATH".to_string(), "/usr/bin".to_string());
+        assert!(matches!(
+            reserved.verify_env(),
+            Err(RunnerEnvError::Reserved("PATH"))
+        ));
+        // even unchecked, the reserved variables keep their value
+        assert!(args(reserved)
+            .windows(3)
+            .any(|w| w == ["--setenv", "PATH", "/bin"]));
+
+        let mut invalid = manifest("env");
+        invalid.env.insert("A=B".to_string(), String::new());
+        assert!(matches!(
+            invalid.verify_env(),
+            Err(RunnerEnvError::InvalidName("A=B"))
+        ));
+    }
+
+    #[test]
+    fn network() {
+        let args = |network: bool| {
+            let mut manifest = manifest("network");
+            manifest.network = network;
+            let runner = runner(manifest);
+            let mut run = Run::new(&runner);
+            runner
+                .entry_command(&mut run, &RunRequest::new())
+                .unwrap()
+                .build_args()
+                .unwrap()
+        };
+        let isolated = args(false);
+        assert!(isolated.iter().any(|arg| arg == "--unshare-all"));
+        assert!(!isolated.iter().any(|arg| arg == "--share-net"));
+        let shared = args(true);
+        assert!(shared.iter().any(|arg| arg == "--unshare-all"));
+        assert!(shared.iter().any(|arg| arg == "--share-net"));
+    }
+
+    #[test]
+    fn files_root_per_run() {
+        let runner = runner(manifest("files_root"));
+        let first = Run::new(&runner);
+        let second = Run::new(&runner);
+        assert_ne!(first.files_root, second.files_root);
+        assert_eq!(first.files_root.parent(), Some(Path::new(FILES_DIR)));
+        assert_eq!(second.files_root.parent(), Some(Path::new(FILES_DIR)));
+    }
+
+    #[test]
+    fn launch_invalid_request() {
+        let mut manifest = manifest("invalid_request");
+        manifest.max_submitted_size = 4;
+        let runner = runner(manifest);
+
+        let mut request = RunRequest::new();
+        request.with_file("../escape", "");
+        let error = runner.launch(&request).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        let mut request = RunRequest::new();
+        request.with_file("main.c", "int main() {}");
+        let error = runner.launch(&request).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires bwrap"]
+    async fn launch() {
+        let mut runner = runner(manifest("launch"));
+        runner.manifest.entry = PathBuf::from("/bin/true");
+        runner
+            .bin_deps
+            .insert("true".to_string(), PathBuf::from("/usr/bin/true"));
+        runner.absolute_file_deps.insert(PathBuf::from("/usr"));
+        runner.absolute_file_deps.insert(PathBuf::from("/lib"));
+        runner.absolute_file_deps.insert(PathBuf::from("/lib64"));
+        let mut run = runner.launch(&RunRequest::new()).unwrap();
+        assert!(matches!(run.state, RunState::Running(_)));
+        let status = run.child.as_mut().unwrap().wait().await.unwrap();
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires bwrap"]
+    async fn launch_namespaces() {
+        use kincir_bwrap::NsFlags;
+
+        use std::os::unix::fs::PermissionsExt;
+
+        // running for a moment, so that its namespaces can be read
+        let dir = scratch::ScratchDir::create("/entry").unwrap();
+        let entry = dir.host().join("entry");
+        std::fs::write(&entry, "#!/bin/sh\nsleep 0.5\n").unwrap();
+        std::fs::set_permissions(&entry, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut runner = runner(manifest("launch_namespaces"));
+        runner.manifest.entry = entry.clone();
+        for (name, path) in [("sh", "/bin/sh"), ("sleep", "/bin/sleep")] {
+            runner
+                .bin_deps
+                .insert(name.to_string(), PathBuf::from(path));
+        }
+        for path in [
+            dir.host(),
+            Path::new("/usr"),
+            Path::new("/lib"),
+            Path::new("/lib64"),
+        ] {
+            runner.absolute_file_deps.insert(path.to_path_buf());
+        }
+        let mut run = runner.launch(&RunRequest::new()).unwrap();
+        run.await_completion(&runner).await.unwrap();
+        let RunState::Complete(output) = &run.state else {
+            panic!("the run should be complete, not {:?}", run.state);
+        };
+        // requested with `--unshare-all`, without the network
+        let requested = NsFlags::IPC | NsFlags::PID | NsFlags::NET | NsFlags::UTS;
+        assert!(
+            output.namespaces.contains(requested),
+            "{:?}",
+            output.namespaces
+        );
+    }
+
+    #[tokio::test]
+    async fn failed() {
+        let finished = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut runner = runner(manifest("failed"));
+        let count = finished.clone();
+        runner.on_complete(move |_| {
+            count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        });
+        let mut run = Run::new(&runner);
+        run.trace = Some(scratch::ScratchDir::create(TRACE_DIR).unwrap());
+        let trace = run.trace.as_ref().unwrap().host().to_path_buf();
+        // feeding the stdin fails
+        run.stdin = Some(RunStdin::File(trace.join("missing")));
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("sleep 30");
+        run.spawn(command).unwrap();
+
+        let started = std::time::Instant::now();
+        let error = run.await_completion(&runner).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(matches!(run.state, RunState::Failed(_)));
+        assert!(run.finished_at.is_some());
+        assert_eq!(finished.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(!trace.exists());
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let mut manifest = manifest("timeout");
+        manifest.timeout = Duration::from_millis(200);
+        let runner = runner(manifest);
+
+        let mut run = Run::new(&runner);
+        let pid_file = std::env::temp_dir().join(format!("kincir-{}", run.id));
+        let mut command = std::process::Command::new("sh");
+        // the background sleep is part of the process group, and must be killed too
+        command
+            .arg("-c")
+            .arg(format!("sleep 30 & echo $! > {}; wait", pid_file.display()));
+        run.spawn(command).unwrap();
+        let started = std::time::Instant::now();
+        run.await_completion(&runner).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(matches!(run.state, RunState::TimedOut));
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        std::fs::remove_file(&pid_file).unwrap();
+        // the orphan is reaped by the init process at some point, until then it is a zombie
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(stat.map_or(true, |stat| stat.contains(") Z ")));
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let runner = runner(manifest("cancel"));
+        let mut run = Run::new(&runner);
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("sleep 30 & wait");
+        run.spawn(command).unwrap();
+        let cancel = run.cancel_handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+        let started = std::time::Instant::now();
+        run.await_completion(&runner).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(matches!(run.state, RunState::Cancelled));
+
+        // too late: the completed run stays complete
+        let mut run = Run::new(&runner);
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("exit 0");
+        run.spawn(command).unwrap();
+        run.await_completion(&runner).await.unwrap();
+        run.cancel_handle().cancel();
+        assert!(matches!(run.state, RunState::Complete(_)));
+    }
+
+    #[test]
+    fn trace() {
+        let mut manifest = manifest("trace");
+        manifest.show_trace = true;
+        let mut run = Run::new(&runner(manifest));
+        let empty = output::CapturedOutput::Bytes(Vec::new());
+        assert_eq!(run.read_trace().unwrap(), empty);
+
+        let dir = scratch::ScratchDir::create(TRACE_DIR).unwrap();
+        let file = dir.host().join(TRACE_FILE_NAME);
+        run.trace = Some(dir);
+        assert_eq!(run.read_trace().unwrap(), empty);
+
+        std::fs::write(&file, "step 1\n").unwrap();
+        let trace = output::CapturedOutput::Bytes(b"step 1\n".to_vec());
+        assert_eq!(run.read_trace().unwrap(), trace);
+
+        run.show_trace = false;
+        assert_eq!(run.read_trace().unwrap(), empty);
+        run.show_trace = true;
+
+        let big = vec![b'a'; usize::try_from(MAX_TRACE_SIZE).unwrap() + 10];
+        std::fs::write(&file, &big).unwrap();
+        let output::CapturedOutput::Bytes(trace) = run.read_trace().unwrap() else {
+            panic!("the trace should be in memory");
+        };
+        assert_eq!(trace.len(), big.len() - 10);
+
+        // a symlink planted by the sandbox must not be followed
+        std::fs::remove_file(&file).unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", &file).unwrap();
+        assert_eq!(run.read_trace().unwrap(), empty);
+    }
+
+    #[tokio::test]
+    async fn exit_status() {
+        let mut manifest = manifest("exit-status");
+        manifest.exit_status = HashMap::from([
+            (1, "missing files".to_string()),
+            (3, "doesn't compile".to_string()),
+        ]);
+        assert!(manifest.verify_exit_status().is_ok());
+        let runner = runner(manifest);
+
+        let mut statuses = Vec::new();
+        for script in ["exit 0", "exit 3", "exit 4", "kill -9 $$"] {
+            let mut run = Run::new(&runner);
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(script);
+            run.spawn(command).unwrap();
+            run.await_completion(&runner).await.unwrap();
+            let RunState::Complete(output) = run.state else {
+                panic!("the run should be complete");
+            };
+            statuses.push((output.successful, output.status));
+        }
+        assert_eq!(
+            statuses,
+            [
+                (true, "Exit code: 0".to_string()),
+                (false, "doesn't compile".to_string()),
+                (false, "Exit code: 4".to_string()),
+                (false, "Signal: SIGKILL".to_string()),
+            ]
+        );
+
+        let mut zero = runner.manifest.clone();
+        zero.exit_status.insert(0, "success".to_string());
+        assert!(matches!(
+            zero.verify_exit_status(),
+            Err(RunnerExitStatusError::SuccessCode("success"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn completion() {
+        let runner = runner(manifest("completion"));
+        let mut run = Run::new(&runner);
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("echo out; exit 3");
+        run.spawn(command).unwrap();
+        run.await_completion(&runner).await.unwrap();
+        let RunState::Complete(output) = &run.state else {
+            panic!("the run should be complete, not {:?}", run.state);
+        };
+        assert!(!output.successful);
+        assert_eq!(output.status, "Exit code: 3");
+        assert_eq!(
+            output.stdout,
+            output::CapturedOutput::Bytes(b"out\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn output_limit() {
+        let mut manifest = manifest("output_limit");
+        manifest.max_output_bytes = 16;
+        let runner = runner(manifest);
+        let mut run = Run::new(&runner);
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("yes | head -c 1000000");
+        run.spawn(command).unwrap();
+        run.await_completion(&runner).await.unwrap();
+        let RunState::Complete(output) = &run.state else {
+            panic!("the run should be complete, not {:?}", run.state);
+        };
+        assert!(output.truncated);
+        assert_eq!(
+            output.stdout,
+            output::CapturedOutput::Bytes(b"y\n".repeat(8))
+        );
+    }
+
+    #[tokio::test]
+    async fn stdin() {
+        let runner = runner(manifest("stdin"));
+        let run_with = |stdin: Option<RunStdin>, script: &str| {
+            let mut run = Run::new(&runner);
+            run.stdin = stdin;
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(script);
+            run.spawn(command).unwrap();
+            run
+        };
+        let stdout = |run: &Run| match &run.state {
+            RunState::Complete(output) => output.stdout.clone(),
+            state => panic!("the run should be complete, not {state:?}"),
+        };
+
+        let mut run = run_with(Some(RunStdin::Bytes(b"hello\n".to_vec())), "cat");
+        run.await_completion(&runner).await.unwrap();
+        assert_eq!(
+            stdout(&run),
+            output::CapturedOutput::Bytes(b"hello\n".to_vec())
+        );
+
+        // without any input, the stdin is empty instead of the one of the service
+        let mut run = run_with(None, "cat");
+        run.await_completion(&runner).await.unwrap();
+        assert_eq!(stdout(&run), output::CapturedOutput::Bytes(Vec::new()));
+
+        // bigger than a pipe, streamed from a file
+        let input = std::env::temp_dir().join(format!("kincir-stdin-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&input, vec![b'a'; 1 << 20]).unwrap();
+        let mut run = run_with(Some(RunStdin::File(input.clone())), "wc -c");
+        run.await_completion(&runner).await.unwrap();
+        std::fs::remove_file(&input).unwrap();
+        let output::CapturedOutput::Bytes(count) = stdout(&run) else {
+            unreachable!()
+        };
+        assert_eq!(String::from_utf8(count).unwrap().trim(), "1048576");
+
+        // the input left is discarded if the sandbox doesn't read it
+        let mut run = run_with(Some(RunStdin::Bytes(vec![b'a'; 1 << 20])), "true");
+        run.await_completion(&runner).await.unwrap();
+        assert!(matches!(run.state, RunState::Complete(_)));
+    }
+
+    #[tokio::test]
+    async fn dirs_removed_when_finished() {
+        let mut manifest = manifest("dirs_removed");
+        manifest.scratch_dir = Some(PathBuf::from("/scratch"));
+        manifest.timeout = Duration::from_millis(200);
+        let runner = runner(manifest);
+
+        for script in ["true", "sleep 30"] {
+            let mut run = Run::new(&runner);
+            run.step_command("sh").unwrap();
+            run.trace = Some(scratch::ScratchDir::create(TRACE_DIR).unwrap());
+            let scratch = run.scratch.as_ref().unwrap().host().to_path_buf();
+            let trace = run.trace.as_ref().unwrap().host().to_path_buf();
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(script);
+            run.spawn(command).unwrap();
+            run.await_completion(&runner).await.unwrap();
+            assert!(!trace.exists(), "{script} left its trace directory");
+            // kept for the next steps
+            assert!(scratch.exists());
+            run.end();
+            assert!(!scratch.exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn steps_share_scratch() {
+        let mut manifest = manifest("steps");
+        manifest.scratch_dir = Some(PathBuf::from("/scratch"));
+        let runner = runner(manifest);
+        let mut run = runner.new_run();
+        run.step_command("sh").unwrap();
+        let host = run.scratch.as_ref().unwrap().host().to_path_buf();
+
+        for script in ["echo artifact > \"$0\"/a.out", "cat \"$0\"/a.out"] {
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(script).arg(&host);
+            run.spawn(command).unwrap();
+            // a step can't be launched while the previous one runs
+            let error = runner
+                .launch_step(&mut run, &RunRequest::new(), "/bin/true", [""; 0])
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            run.await_completion(&runner).await.unwrap();
+        }
+        let RunState::Complete(output) = &run.state else {
+            panic!("the run should be complete, not {:?}", run.state);
+        };
+        assert_eq!(
+            output.stdout,
+            output::CapturedOutput::Bytes(b"artifact\n".to_vec())
+        );
+
+        let other = self::runner(self::manifest("other"));
+        let error = other
+            .launch_step(&mut run, &RunRequest::new(), "/bin/true", [""; 0])
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires bwrap"]
+    async fn launch_steps() {
+        let mut manifest = manifest("launch_steps");
+        manifest.scratch_dir = Some(PathBuf::from("/scratch"));
+        let mut runner = runner(manifest);
+        runner
+            .bin_deps
+            .insert("sh".to_string(), PathBuf::from("/bin/sh"));
+        for path in ["/usr", "/lib", "/lib64"] {
+            runner.absolute_file_deps.insert(PathBuf::from(path));
+        }
+        let mut run = runner.new_run();
+        for script in ["echo artifact > /scratch/a.out", "cat /scratch/a.out"] {
+            runner
+                .launch_step(&mut run, &RunRequest::new(), "/bin/sh", ["-c", script])
+                .unwrap();
+            run.await_completion(&runner).await.unwrap();
+        }
+        let RunState::Complete(output) = &run.state else {
+            panic!("the run should be complete, not {:?}", run.state);
+        };
+        assert_eq!(
+            output.stdout,
+            output::CapturedOutput::Bytes(b"artifact\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn scratch_persists_across_steps() {
+        let mut manifest = manifest("scratch");
+        manifest.scratch_dir = Some(PathBuf::from("/scratch"));
+        let runner = runner(manifest);
+
+        let mut run = Run::new(&runner);
+        let compile = run.step_command("cc").unwrap().build_args().unwrap();
+        let host = run.scratch.as_ref().unwrap().host().to_path_buf();
+        std::fs::write(host.join("a.out"), "artifact").unwrap();
+
+        let execute = run.step_command("./a.out").unwrap().build_args().unwrap();
+        assert_eq!(compile[..3], execute[..3]);
+        assert_eq!(
+            execute[..3],
+            [
+                OsStr::new("--bind"),
+                host.as_os_str(),
+                OsStr::new("/scratch")
+            ]
+        );
+        assert_eq!(std::fs::read(host.join("a.out")).unwrap(), b"artifact");
+
+        let mut other = Run::new(&runner);
+        other.step_command("cc").unwrap();
+        assert_ne!(other.scratch.as_ref().unwrap().host(), host);
+
+        drop(run);
+        assert!(!host.exists());
     }
 }
@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+/// A cgroup (v2) created for a sandbox.
+///
+/// The cgroup is removed when this is dropped, which will only succeed if every process inside of
+/// it has exited.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create a new cgroup named `name` as a child of `parent`.
+    ///
+    /// `parent` must be a cgroup v2 directory on which the service has write access (usually a
+    /// subtree delegated by systemd), with the needed controllers enabled in its
+    /// `cgroup.subtree_control`
+    pub fn create(parent: impl AsRef<Path>, name: &str) -> std::io::Result<Self> {
+        let path = parent.as_ref().join(name);
+        std::fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the maximum number of tasks (threads and processes) inside of this cgroup
+    pub fn set_pids_max(&self, max: u64) -> std::io::Result<()> {
+        self.write("pids.max", max.to_string())
+    }
+
+    /// Set the maximum amount of memory (in bytes) used by the processes of this cgroup, going
+    /// over it gets them killed by the OOM killer (see [`Cgroup::oom_killed`]).
+    ///
+    /// The swap is disabled when the memory is limited (if swap accounting is available),
+    /// otherwise the limit could be bypassed by swapping
+    pub fn set_memory_max(&self, max: u64) -> std::io::Result<()> {
+        self.write("memory.max", max.to_string())?;
+        match self.write("memory.swap.max", "0".to_string()) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Whether a process of this cgroup was killed because the cgroup ran out of memory
+    pub fn oom_killed(&self) -> std::io::Result<bool> {
+        let events = std::fs::read_to_string(self.path.join("memory.events"))?;
+        Ok(events
+            .lines()
+            .filter_map(|line| line.strip_prefix("oom_kill "))
+            .any(|count| count.trim().parse::<u64>().is_ok_and(|count| count > 0)))
+    }
+
+    /// Install a pre-exec hook on the given `command` moving the child process into this cgroup
+    /// before it runs the program, so that it never runs outside of it
+    pub fn install(&self, command: &mut std::process::Command) -> std::io::Result<()> {
+        use std::{io::Write, os::unix::process::CommandExt};
+
+        // opened beforehand, the hook only writes to it (and it is closed on exec)
+        let procs = std::fs::OpenOptions::new()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))?;
+        // SAFETY: write is async-signal-safe, and nothing is allocated inside the hook
+        unsafe {
+            // `0` is the process doing the write
+            command.pre_exec(move || (&procs).write_all(b"0"));
+        }
+        Ok(())
+    }
+
+    /// Move the process with the given `pid` into this cgroup.
+    ///
+    /// Every process spawned afterwards by that process will also live inside this cgroup
+    pub fn add_process(&self, pid: u32) -> std::io::Result<()> {
+        self.write("cgroup.procs", pid.to_string())
+    }
+
+    fn write(&self, file: &str, value: String) -> std::io::Result<()> {
+        std::fs::write(self.path.join(file), value)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            log::warn!("failed to remove cgroup {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oom_killed() {
+        let parent = std::env::temp_dir().join(format!("kincir-cgroup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&parent).unwrap();
+        let cgroup = Cgroup::create(&parent, "run").unwrap();
+        let events = cgroup.path().join("memory.events");
+        std::fs::write(&events, "low 0\nhigh 0\nmax 0\noom 0\noom_kill 0\n").unwrap();
+        let before = cgroup.oom_killed().unwrap();
+        std::fs::write(&events, "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n").unwrap();
+        let after = cgroup.oom_killed().unwrap();
+        std::fs::remove_file(&events).unwrap();
+        drop(cgroup);
+        std::fs::remove_dir(&parent).unwrap();
+        assert!(!before);
+        assert!(after);
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use super::Runner;
 
 pub type DistroHandler = fn(&mut Runner) -> Result<(), Box<dyn std::error::Error>>;
@@ -6,17 +8,230 @@ pub type DistroName = &'static str;
 /// This will allow the use of specific handling for some linux distros
 /// (specifically nixos since they work in a weird way where the /nix folder will probably be needed)
 ///
-/// This will be a mapping to the `DISTRIB_ID` in `/etc/lsb-release` file to a function that will
-/// take a runner and modify some flags/feature/binds to allow smooth execution
+/// This will be a mapping of the distro id (as returned by [`detect_distro`], meaning lowercased)
+/// to a function that will take a runner and modify some flags/feature/binds to allow smooth
+/// execution. The distros that aren't listed here use [`generic_handling`].
+///
+/// To support another distro, add its id here with its own handler. A handler usually calls
+/// [`generic_handling`] and then adds what is specific to the distro, for example:
+///
+/// ```ignore
+/// fn gentoo_handling(runner: &mut Runner) -> Result<(), Box<dyn std::error::Error>> {
+///     generic_handling(runner)?;
+///     runner.absolute_file_deps.insert(PathBuf::from("/usr/lib/llvm"));
+///     Ok(())
+/// }
+/// ```
 pub static DISTRO_HANDLERS: phf::Map<DistroName, DistroHandler> = phf::phf_map! {
     "nixos" => nixos_handling,
-    "Ubuntu" => ubuntu_handling,
+    "ubuntu" => ubuntu_handling,
 };
 
+/// The files describing the distro, as specified by `os-release(5)`: the first one that exists is
+/// used
+const OS_RELEASE_PATHS: &[&str] = &["/etc/os-release", "/usr/lib/os-release"];
+
+/// The file describing the distro on older systems, only used if there is no `os-release`
+const LSB_RELEASE_PATH: &str = "/etc/lsb-release";
+
+/// Detect the distro of the host: the `ID` of the `os-release` file, or the `DISTRIB_ID` of
+/// `/etc/lsb-release` if there is none. The id is lowercased (`Ubuntu` becomes `ubuntu`).
+///
+/// Returns `None` if none of these files exist (for example on a minimal system), or if they
+/// don't contain an id
+pub fn detect_distro() -> Option<String> {
+    detect_distro_from(
+        OS_RELEASE_PATHS.iter().map(Path::new),
+        Path::new(LSB_RELEASE_PATH),
+    )
+}
+
+/// Same as [`detect_distro`], with the given files
+fn detect_distro_from<'a>(
+    os_release: impl IntoIterator<Item = &'a Path>,
+    lsb_release: &Path,
+) -> Option<String> {
+    let os_release = os_release
+        .into_iter()
+        .find_map(|path| std::fs::read_to_string(path).ok());
+    let id = match os_release {
+        Some(contents) => release_value(&contents, "ID"),
+        None => release_value(&std::fs::read_to_string(lsb_release).ok()?, "DISTRIB_ID"),
+    }?;
+    Some(id.to_lowercase())
+}
+
+/// The library directories bound by [`generic_handling`], if they exist on the host
+const GENERIC_LIBRARY_DIRS: &[&str] = &[
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib32",
+    "/usr/lib64",
+];
+
+/// The handler of the given distro (as returned by [`detect_distro`]), or [`generic_handling`]
+/// if the distro has no specific handler or is unknown
+pub fn distro_handler(distro: Option<&str>) -> DistroHandler {
+    distro
+        .and_then(|distro| DISTRO_HANDLERS.get(distro.to_lowercase().as_str()))
+        .copied()
+        .unwrap_or(generic_handling)
+}
+
+/// The handler of the host, see [`detect_distro`] and [`distro_handler`]
+pub fn host_distro_handler() -> DistroHandler {
+    distro_handler(detect_distro().as_deref())
+}
+
+/// Find the value of `key` in a `KEY=value` file (such as `os-release`), without its quotes.
+///
+/// Comments and empty values are ignored
+fn release_value(contents: &str, key: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let (k, value) = line.trim().split_once('=')?;
+        if k.trim() != key {
+            return None;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+            .unwrap_or(value);
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// The handling of the distros following the usual layout: bind the library directories
+/// (holding the shared libraries and the dynamic linker) read-only.
+///
+/// The directories that don't exist on the host are skipped, and the ones that are symlinks
+/// (such as `/lib -> usr/lib` on merged-usr systems) are bound to their target
+pub fn generic_handling(runner: &mut Runner) -> Result<(), Box<dyn std::error::Error>> {
+    for dir in GENERIC_LIBRARY_DIRS {
+        if Path::new(dir).is_dir() {
+            runner.absolute_file_deps.insert(PathBuf::from(dir));
+        }
+    }
+    Ok(())
+}
+
+/// The binaries of nixos live in the nix store (and so do their libraries), which is needed on
+/// top of the usual directories
 fn nixos_handling(runner: &mut Runner) -> Result<(), Box<dyn std::error::Error>> {
-    todo!()
+    generic_handling(runner)?;
+    runner
+        .absolute_file_deps
+        .insert(PathBuf::from("/nix/store"));
+    Ok(())
 }
 
+/// Nothing specific yet, ubuntu follows the usual layout
 fn ubuntu_handling(runner: &mut Runner) -> Result<(), Box<dyn std::error::Error>> {
-    todo!()
+    generic_handling(runner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NIXOS: &str = r#"ANSI_COLOR="1;34"
+BUG_REPORT_URL="https://github.com/NixOS/nixpkgs/issues"
+BUILD_ID="24.11.20241201.a1b2c3d"
+DOCUMENTATION_URL="https://nixos.org/learn.html"
+HOME_URL="https://nixos.org/"
+ID=nixos
+LOGO="nix-snowflake"
+NAME=NixOS
+PRETTY_NAME="NixOS 24.11 (Vicuna)"
+VERSION_ID="24.11"
+"#;
+
+    const UBUNTU: &str = r#"PRETTY_NAME="Ubuntu 24.04.1 LTS"
+NAME="Ubuntu"
+VERSION_ID="24.04"
+VERSION="24.04.1 LTS (Noble Numbat)"
+VERSION_CODENAME=noble
+ID=ubuntu
+ID_LIKE=debian
+UBUNTU_CODENAME=noble
+"#;
+
+    const UBUNTU_LSB: &str = "DISTRIB_ID=Ubuntu
+DISTRIB_RELEASE=24.04
+DISTRIB_CODENAME=noble
+DISTRIB_DESCRIPTION=\"Ubuntu 24.04.1 LTS\"
+";
+
+    /// Write the given release files in a new directory, and detect the distro from them
+    fn detect(os_release: Option<&str>, lsb_release: Option<&str>) -> Option<String> {
+        let dir = std::env::temp_dir().join(format!("kincir-distro-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        if let Some(contents) = os_release {
+            std::fs::write(dir.join("os-release"), contents).unwrap();
+        }
+        if let Some(contents) = lsb_release {
+            std::fs::write(dir.join("lsb-release"), contents).unwrap();
+        }
+        let distro = detect_distro_from(
+            [
+                dir.join("missing").as_path(),
+                dir.join("os-release").as_path(),
+            ],
+            &dir.join("lsb-release"),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        distro
+    }
+
+    #[test]
+    fn os_release() {
+        assert_eq!(detect(Some(NIXOS), None).as_deref(), Some("nixos"));
+        assert_eq!(
+            detect(Some(UBUNTU), Some("DISTRIB_ID=Other")).as_deref(),
+            Some("ubuntu")
+        );
+        assert_eq!(
+            detect(Some("ID=\"fedora\"\n"), None).as_deref(),
+            Some("fedora")
+        );
+        assert!(DISTRO_HANDLERS.contains_key("nixos"));
+        assert!(DISTRO_HANDLERS.contains_key("ubuntu"));
+        assert!(std::ptr::fn_addr_eq(
+            distro_handler(Some("fedora")),
+            generic_handling as DistroHandler
+        ));
+    }
+
+    #[test]
+    fn lsb_release() {
+        assert_eq!(detect(None, Some(UBUNTU_LSB)).as_deref(), Some("ubuntu"));
+        assert!(std::ptr::fn_addr_eq(
+            distro_handler(Some("Ubuntu")),
+            ubuntu_handling as DistroHandler
+        ));
+    }
+
+    #[test]
+    fn generic() {
+        assert!(std::ptr::fn_addr_eq(
+            distro_handler(None),
+            generic_handling as DistroHandler
+        ));
+        let mut runner = crate::runner::tests::runner(crate::runner::tests::manifest("generic"));
+        generic_handling(&mut runner).unwrap();
+        assert!(runner.absolute_file_deps.contains(Path::new("/usr/lib")));
+        assert!(runner
+            .absolute_file_deps
+            .iter()
+            .all(|dir| GENERIC_LIBRARY_DIRS.contains(&dir.to_str().unwrap()) && dir.exists()));
+    }
+
+    #[test]
+    fn missing() {
+        assert_eq!(detect(None, None), None);
+        assert_eq!(detect(Some("NAME=Linux\n"), None), None);
+    }
 }
@@ -0,0 +1,320 @@
+use std::{
+    os::unix::{ffi::OsStrExt, fs::FileExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The type of the program header giving the path of the interpreter
+const PT_INTERP: u32 = 3;
+
+/// The maximum length of the interpreter path that is read (`PATH_MAX`)
+const MAX_INTERPRETER_LEN: u64 = 4096;
+
+/// Find the program interpreter (the dynamic linker, such as `/lib64/ld-linux-x86-64.so.2`) of
+/// the given binary, from the `PT_INTERP` program header of its ELF.
+///
+/// Returns `None` if the binary doesn't need one: it is statically linked, or isn't an ELF at all
+/// (such as a script). Both 32 and 64 bit ELF are supported, in either endianness
+pub fn interpreter(binary: &Path) -> std::io::Result<Option<PathBuf>> {
+    let file = std::fs::File::open(binary)?;
+    let mut header = [0; 64];
+    let read = read_at_most(&file, &mut header, 0)?;
+    let header = &header[..read];
+    if header.len() < 52 || &header[..4] != b"\x7fELF" {
+        return Ok(None);
+    }
+    let elf = Elf {
+        is_64: match header[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(invalid_elf("unknown class")),
+        },
+        big_endian: match header[5] {
+            1 => false,
+            2 => true,
+            _ => return Err(invalid_elf("unknown data encoding")),
+        },
+    };
+    if elf.is_64 && header.len() < 64 {
+        return Err(invalid_elf("truncated header"));
+    }
+    let (phoff, phentsize, phnum) = if elf.is_64 {
+        (
+            elf.u64(&header[0x20..]),
+            elf.u16(&header[0x36..]),
+            elf.u16(&header[0x38..]),
+        )
+    } else {
+        (
+            elf.u32(&header[0x1c..]).into(),
+            elf.u16(&header[0x2a..]),
+            elf.u16(&header[0x2c..]),
+        )
+    };
+    let min_phentsize = if elf.is_64 { 56 } else { 32 };
+    if phnum != 0 && usize::from(phentsize) < min_phentsize {
+        return Err(invalid_elf("program headers too small"));
+    }
+
+    let mut entry = vec![0; usize::from(phentsize)];
+    for index in 0..u64::from(phnum) {
+        let offset = phoff
+            .checked_add(index * u64::from(phentsize))
+            .ok_or_else(|| invalid_elf("program headers out of bounds"))?;
+        file.read_exact_at(&mut entry, offset)?;
+        if elf.u32(&entry) != PT_INTERP {
+            continue;
+        }
+        let (offset, size) = if elf.is_64 {
+            (elf.u64(&entry[8..]), elf.u64(&entry[32..]))
+        } else {
+            (elf.u32(&entry[4..]).into(), elf.u32(&entry[16..]).into())
+        };
+        let mut path = vec![0; usize::try_from(size.min(MAX_INTERPRETER_LEN)).unwrap_or(0)];
+        let read = read_at_most(&file, &mut path, offset)?;
+        path.truncate(read);
+        // the path is nul-terminated
+        if let Some(end) = path.iter().position(|&b| b == 0) {
+            path.truncate(end);
+        }
+        if path.is_empty() {
+            return Err(invalid_elf("empty interpreter"));
+        }
+        return Ok(Some(PathBuf::from(std::ffi::OsStr::from_bytes(&path))));
+    }
+    Ok(None)
+}
+
+/// The layout of an ELF file
+struct Elf {
+    is_64: bool,
+    big_endian: bool,
+}
+
+impl Elf {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn u64(&self, bytes: &[u8]) -> u64 {
+        let mut array = [0; 8];
+        array.copy_from_slice(&bytes[..8]);
+        if self.big_endian {
+            u64::from_be_bytes(array)
+        } else {
+            u64::from_le_bytes(array)
+        }
+    }
+}
+
+fn invalid_elf(reason: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid ELF: {reason}"),
+    )
+}
+
+/// Read into `buf` from `offset`, stopping early at the end of the file
+fn read_at_most(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read_at(&mut buf[read..], offset + read as u64) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Find the shared libraries needed by the given binary (including the dynamic linker), by
+/// running `ldd` on it.
+///
+/// A binary that isn't dynamically linked (a static binary or a script) needs no library. `ldd`
+/// is given up on after `timeout`, like the other lookups done when loading a runner
+pub fn shared_libraries(binary: &Path, timeout: Duration) -> Result<Vec<PathBuf>, LibraryError> {
+    let path = binary.to_path_buf();
+    let output = super::with_timeout(timeout, move || {
+        std::process::Command::new("ldd")
+            .arg(path)
+            .stdin(std::process::Stdio::null())
+            .output()
+    })
+    .ok_or(LibraryError::Timeout(timeout))?
+    .map_err(LibraryError::Ldd)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        if stdout.contains("not a dynamic executable")
+            || stderr.contains("not a dynamic executable")
+        {
+            return Ok(Vec::new());
+        }
+        return Err(LibraryError::Failed(stderr.trim().to_string()));
+    }
+    parse_ldd(&stdout)
+}
+
+/// Parse the output of `ldd`, such as:
+///
+/// ```text
+///     linux-vdso.so.1 (0x00007ffd7d5f5000)
+///     libc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f0a3c000000)
+///     /lib64/ld-linux-x86-64.so.2 (0x00007f0a3c400000)
+/// ```
+///
+/// The libraries without a path (such as the vdso, which is provided by the kernel) are skipped
+fn parse_ldd(output: &str) -> Result<Vec<PathBuf>, LibraryError> {
+    let mut libraries = Vec::new();
+    for line in output.lines() {
+        let line = line.trim();
+        let path = match line.split_once("=>") {
+            Some((name, resolved)) => {
+                let resolved = resolved.trim();
+                if resolved.starts_with("not found") {
+                    return Err(LibraryError::Missing(name.trim().to_string()));
+                }
+                resolved
+            }
+            None => line,
+        };
+        // strip the load address
+        let path = path.split(" (").next().unwrap_or_default().trim();
+        if path.starts_with('/') {
+            libraries.push(PathBuf::from(path));
+        }
+    }
+    Ok(libraries)
+}
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Ldd(std::io::Error),
+    Failed(String),
+    Missing(String),
+    Timeout(Duration),
+}
+
+impl std::fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ldd(e) => writeln!(f, "failed to run ldd: {e}"),
+            Self::Failed(stderr) => writeln!(f, "ldd failed: {stderr}"),
+            Self::Missing(library) => writeln!(f, "missing shared library `{library}`"),
+            Self::Timeout(timeout) => {
+                writeln!(f, "ldd took more than {}s", timeout.as_secs_f64())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let output = "\tlinux-vdso.so.1 (0x00007ffd7d5f5000)
+\tlibtinfo.so.6 => /lib/x86_64-linux-gnu/libtinfo.so.6 (0x00007f8d4ba45000)
+\tlibc.so.6 => /lib/x86_64-linux-gnu/libc.so.6 (0x00007f8d4b863000)
+\t/lib64/ld-linux-x86-64.so.2 (0x00007f8d4bbca000)
+";
+        assert_eq!(
+            parse_ldd(output).unwrap(),
+            [
+                PathBuf::from("/lib/x86_64-linux-gnu/libtinfo.so.6"),
+                PathBuf::from("/lib/x86_64-linux-gnu/libc.so.6"),
+                PathBuf::from("/lib64/ld-linux-x86-64.so.2"),
+            ]
+        );
+
+        let output = "\tlibmissing.so.1 => not found\n";
+        assert!(matches!(
+            parse_ldd(output),
+            Err(LibraryError::Missing(library)) if library == "libmissing.so.1"
+        ));
+    }
+
+    /// A 64 bit little-endian ELF with the given program headers (type, content)
+    fn elf64(headers: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut elf = vec![0; 64];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        let mut data_offset = 64 + 56 * headers.len();
+        let mut data = Vec::new();
+        for (kind, content) in headers {
+            let mut entry = vec![0; 56];
+            entry[..4].copy_from_slice(&kind.to_le_bytes());
+            entry[8..16].copy_from_slice(&(data_offset as u64).to_le_bytes());
+            entry[32..40].copy_from_slice(&(content.len() as u64).to_le_bytes());
+            elf.extend(entry);
+            data.extend_from_slice(content);
+            data_offset += content.len();
+        }
+        elf.extend(data);
+        elf
+    }
+
+    fn interpreter_of(contents: &[u8]) -> std::io::Result<Option<PathBuf>> {
+        let path = std::env::temp_dir().join(format!("kincir-elf-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        let interpreter = interpreter(&path);
+        std::fs::remove_file(&path).unwrap();
+        interpreter
+    }
+
+    #[test]
+    fn elf_interpreter() {
+        let elf = elf64(&[(1, b"code"), (PT_INTERP, b"/lib/ld-test.so.1\0")]);
+        assert_eq!(
+            interpreter_of(&elf).unwrap(),
+            Some(PathBuf::from("/lib/ld-test.so.1"))
+        );
+        // statically linked
+        assert_eq!(interpreter_of(&elf64(&[(1, b"code")])).unwrap(), None);
+        assert_eq!(interpreter_of(b"#!/bin/sh\necho hello\n").unwrap(), None);
+        // truncated program headers
+        assert!(interpreter_of(&elf[..100]).is_err());
+
+        let sh = interpreter(Path::new("/bin/sh")).unwrap().unwrap();
+        assert!(sh.exists());
+        assert!(sh.to_string_lossy().contains("ld"));
+    }
+
+    #[test]
+    fn ldd() {
+        let libraries = shared_libraries(Path::new("/bin/sh"), Duration::from_secs(5)).unwrap();
+        assert!(libraries.iter().all(|library| library.exists()));
+        assert!(libraries.iter().any(|library| library
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("libc."))));
+
+        // a script isn't linked to anything
+        let script = std::env::temp_dir().join(format!("kincir-script-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&script, "#!/bin/sh\necho hello\n").unwrap();
+        let libraries = shared_libraries(&script, Duration::from_secs(5));
+        std::fs::remove_file(&script).unwrap();
+        assert!(libraries.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,433 @@
+use std::{collections::HashMap, os::unix::process::CommandExt};
+
+use nix::sys::resource::{rlim_t, Resource};
+
+use super::cgroup::Cgroup;
+
+/// The resources limits that will be applied to the sandbox.
+///
+/// The rlimits are set in a pre-exec hook on the bwrap process, meaning that they are inherited
+/// by every process spawned inside the sandbox.
+///
+/// Every limit is optional: an unset limit takes its value from [`ResourceLimits::DEFAULTS`], and
+/// the limits without a default are inherited from the service
+#[serde_with::serde_as]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// The maximum number of threads that can exist at the same time.
+    ///
+    /// This is enforced with `RLIMIT_NPROC`, and `pids.max` if the run is placed inside a cgroup.
+    ///
+    /// # Note
+    ///
+    /// On linux a thread is a process like any other, meaning that this limit also caps the
+    /// number of processes (and the other way around). When both this and a process limit are set,
+    /// the lowest one will be the effective one.
+    ///
+    /// Do also note that `RLIMIT_NPROC` counts every process of the real user id, not only the one
+    /// of the sandbox, and that it isn't enforced for privileged users.
+    #[serde(default)]
+    pub max_threads: Option<u64>,
+
+    /// The maximum number of file descriptors that each process can have opened at the same
+    /// time.
+    ///
+    /// This is enforced with `RLIMIT_NOFILE`
+    #[serde(default)]
+    pub max_open_files: Option<u64>,
+
+    /// The maximum number of processes that can exist at the same time, this is the main
+    /// protection against fork bombs.
+    ///
+    /// This is enforced the same way as [`ResourceLimits::max_threads`] (and has the same
+    /// caveats), the lowest of the two being the effective limit. Since the default applies to
+    /// every process of the user, the service should run as its own dedicated user
+    #[serde(default)]
+    pub max_processes: Option<u64>,
+
+    /// The maximum size (in bytes) of a file written by the sandbox, writing past it fails with
+    /// `EFBIG` (the process also receives a `SIGXFSZ`, which kills it unless it is handled).
+    ///
+    /// This is enforced with `RLIMIT_FSIZE`
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+
+    /// The CPU time (in seconds) that each process can use before being killed by a `SIGXCPU`
+    /// (then by a `SIGKILL` a second later).
+    ///
+    /// This is enforced with `RLIMIT_CPU`. Unlike the timeout of the runner, this counts the
+    /// time spent computing and not the time spent waiting
+    #[serde(default)]
+    pub max_cpu_seconds: Option<u64>,
+
+    /// The maximum amount of memory (in bytes) that the sandbox can use. It can be written with
+    /// a unit, such as `256M` or `1GiB` (see [`HumanBytes`]), and must be positive.
+    ///
+    /// When the run is placed inside a cgroup (see [`super::Run::in_cgroup`]), this is enforced
+    /// with `memory.max`: going over it gets the sandbox killed and the run is reported as out of
+    /// memory. Otherwise this is enforced with `RLIMIT_AS` on each process, meaning that the
+    /// allocations past it fail (which the program may or may not handle), and that the address
+    /// space is limited rather than the memory actually used
+    #[serde(default)]
+    #[serde_as(as = "Option<HumanBytes>")]
+    pub memory_limit: Option<u64>,
+}
+
+/// A number of bytes, either written as an integer or as a string with a unit (`512K`, `256M`,
+/// `1GiB`, `2 TB`...). The units are powers of 1024, and the size must be positive
+pub struct HumanBytes;
+
+impl HumanBytes {
+    /// Parse a size with an optional unit, returning `None` if it is invalid or too large
+    pub fn parse(size: &str) -> Option<u64> {
+        let size = size.trim();
+        let digits = size
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(size.len());
+        let value: u64 = size[..digits].parse().ok()?;
+        let shift = match size[digits..].trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 0,
+            "k" | "kb" | "kib" => 10,
+            "m" | "mb" | "mib" => 20,
+            "g" | "gb" | "gib" => 30,
+            "t" | "tb" | "tib" => 40,
+            _ => return None,
+        };
+        value.checked_mul(1 << shift)
+    }
+}
+
+impl<'de> serde_with::DeserializeAs<'de, u64> for HumanBytes {
+    fn deserialize_as<D: serde::Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        use serde::de::Error;
+
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Human(String),
+        }
+        let size = match <Raw as serde::Deserialize>::deserialize(deserializer)? {
+            Raw::Bytes(size) => size,
+            Raw::Human(size) => HumanBytes::parse(&size)
+                .ok_or_else(|| D::Error::custom(format!("invalid size `{size}`")))?,
+        };
+        if size == 0 {
+            return Err(D::Error::custom("the size must be positive"));
+        }
+        Ok(size)
+    }
+}
+
+impl serde_with::SerializeAs<u64> for HumanBytes {
+    fn serialize_as<S: serde::Serializer>(size: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(*size)
+    }
+}
+
+/// A table of named [`ResourceLimits`] (for example `strict` or `generous`) that manifests can
+/// use with the `resource_profile` key instead of repeating every limit
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct ResourceProfiles(pub HashMap<String, ResourceLimits>);
+
+impl ResourceProfiles {
+    /// Compute the limits of a runner: the limits explicitly set in the manifest, completed by the
+    /// ones of its profile (if any)
+    pub fn resolve<'a>(
+        &self,
+        profile: Option<&'a str>,
+        limits: &ResourceLimits,
+    ) -> Result<ResourceLimits, UnknownProfileError<'a>> {
+        let Some(profile) = profile else {
+            return Ok(limits.clone());
+        };
+        let profile_limits = self.0.get(profile).ok_or(UnknownProfileError(profile))?;
+        Ok(limits.or(profile_limits))
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownProfileError<'a>(pub &'a str);
+
+impl<'a> std::fmt::Display for UnknownProfileError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "unknown resource profile `{}`", self.0)
+    }
+}
+
+impl<'a> std::error::Error for UnknownProfileError<'a> {}
+
+impl ResourceLimits {
+    /// The limits used when neither the manifest nor its resource profile set them
+    pub const DEFAULTS: Self = Self {
+        max_threads: None,
+        max_open_files: None,
+        max_processes: Some(256),
+        max_file_size: Some(64 << 20),
+        max_cpu_seconds: Some(60),
+        memory_limit: None,
+    };
+
+    /// Take the limits of `self`, using the ones of `fallback` for those that aren't set
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            max_threads: self.max_threads.or(fallback.max_threads),
+            max_open_files: self.max_open_files.or(fallback.max_open_files),
+            max_processes: self.max_processes.or(fallback.max_processes),
+            max_file_size: self.max_file_size.or(fallback.max_file_size),
+            max_cpu_seconds: self.max_cpu_seconds.or(fallback.max_cpu_seconds),
+            memory_limit: self.memory_limit.or(fallback.memory_limit),
+        }
+    }
+
+    /// Take the limits of `self`, using the [`ResourceLimits::DEFAULTS`] for those that aren't
+    /// set
+    pub fn with_defaults(&self) -> Self {
+        self.or(&Self::DEFAULTS)
+    }
+
+    /// The effective limit on the number of processes, the lowest of
+    /// [`ResourceLimits::max_threads`] and [`ResourceLimits::max_processes`]
+    fn max_tasks(&self) -> Option<u64> {
+        match (self.max_threads, self.max_processes) {
+            (Some(threads), Some(processes)) => Some(threads.min(processes)),
+            (threads, processes) => threads.or(processes),
+        }
+    }
+
+    /// The list of rlimits (with the soft and hard limit being equal) that correspond to the
+    /// configured limits
+    pub fn rlimits(&self) -> Vec<(Resource, rlim_t)> {
+        let mut out = Vec::new();
+        if let Some(max_tasks) = self.max_tasks() {
+            out.push((Resource::RLIMIT_NPROC, max_tasks as rlim_t));
+        }
+        if let Some(max_open_files) = self.max_open_files {
+            out.push((Resource::RLIMIT_NOFILE, max_open_files as rlim_t));
+        }
+        if let Some(max_file_size) = self.max_file_size {
+            out.push((Resource::RLIMIT_FSIZE, max_file_size as rlim_t));
+        }
+        if let Some(max_cpu_seconds) = self.max_cpu_seconds {
+            out.push((Resource::RLIMIT_CPU, max_cpu_seconds as rlim_t));
+        }
+        if let Some(memory_limit) = self.memory_limit {
+            out.push((Resource::RLIMIT_AS, memory_limit as rlim_t));
+        }
+        out
+    }
+
+    /// Install a pre-exec hook on the given `command` that will apply the rlimits to the child
+    /// process
+    pub fn install(&self, command: &mut std::process::Command) {
+        let rlimits = self.rlimits();
+        if rlimits.is_empty() {
+            return;
+        }
+        // SAFETY: setrlimit is async-signal-safe, and nothing is allocated inside the hook
+        unsafe {
+            command.pre_exec(move || apply_rlimits(&rlimits).map_err(std::io::Error::from));
+        }
+    }
+
+    /// Write the limits that have a cgroup equivalent into the given `cgroup`
+    pub fn apply_to_cgroup(&self, cgroup: &Cgroup) -> std::io::Result<()> {
+        if let Some(max_tasks) = self.max_tasks() {
+            cgroup.set_pids_max(max_tasks)?;
+        }
+        if let Some(memory_limit) = self.memory_limit {
+            cgroup.set_memory_max(memory_limit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set each of the given rlimits for the current process
+pub fn apply_rlimits(rlimits: &[(Resource, rlim_t)]) -> nix::Result<()> {
+    for &(resource, limit) in rlimits {
+        nix::sys::resource::setrlimit(resource, limit, limit)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_limits() {
+        assert!(ResourceLimits::default().rlimits().is_empty());
+    }
+
+    #[test]
+    fn max_threads() {
+        let limits = ResourceLimits {
+            max_threads: Some(32),
+            ..Default::default()
+        };
+        assert_eq!(limits.rlimits(), vec![(Resource::RLIMIT_NPROC, 32)]);
+    }
+
+    #[test]
+    fn max_processes() {
+        let limits = ResourceLimits {
+            max_threads: Some(32),
+            max_processes: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(limits.rlimits(), vec![(Resource::RLIMIT_NPROC, 8)]);
+        let limits = ResourceLimits {
+            max_processes: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(limits.rlimits(), vec![(Resource::RLIMIT_NPROC, 8)]);
+    }
+
+    #[test]
+    fn defaults() {
+        let limits = ResourceLimits {
+            max_processes: Some(8),
+            ..Default::default()
+        }
+        .with_defaults();
+        assert_eq!(
+            limits.rlimits(),
+            vec![
+                (Resource::RLIMIT_NPROC, 8),
+                (Resource::RLIMIT_FSIZE, 64 << 20),
+                (Resource::RLIMIT_CPU, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn file_size() {
+        let dir = std::env::temp_dir().join(format!("kincir-fsize-{}", uuid::Uuid::new_v4()));
+        let limits = ResourceLimits {
+            max_file_size: Some(1024),
+            ..Default::default()
+        };
+        let mut command = std::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg("trap '' XFSZ; head -c 4096 /dev/zero > \"$0\"")
+            .arg(&dir);
+        limits.install(&mut command);
+        let output = command.output().unwrap();
+        let size = std::fs::metadata(&dir).unwrap().len();
+        std::fs::remove_file(&dir).unwrap();
+        assert!(!output.status.success());
+        assert_eq!(size, 1024);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let parse = |yaml: &str| serde_yaml::from_str::<ResourceLimits>(yaml);
+        assert_eq!(
+            parse("memory_limit: 1024").unwrap().memory_limit,
+            Some(1024)
+        );
+        assert_eq!(
+            parse("memory_limit: 256M").unwrap().memory_limit,
+            Some(256 << 20)
+        );
+        assert_eq!(
+            parse("memory_limit: 1 GiB").unwrap().memory_limit,
+            Some(1 << 30)
+        );
+        assert_eq!(parse("{}").unwrap().memory_limit, None);
+        assert!(parse("memory_limit: 0").is_err());
+        assert!(parse("memory_limit: 0M").is_err());
+        assert!(parse("memory_limit: 12 apples").is_err());
+        assert!(parse("memory_limit: 99999999999T").is_err());
+    }
+
+    #[test]
+    fn allocation_over_memory_limit() {
+        let limits = ResourceLimits {
+            memory_limit: Some(16 << 20),
+            ..Default::default()
+        };
+        let mut command = std::process::Command::new("sh");
+        // keeps 64MiB in a variable
+        command
+            .arg("-c")
+            .arg("x=$(head -c 67108864 /dev/zero | tr '\\0' a); echo done");
+        limits.install(&mut command);
+        let output = command.output().unwrap();
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+
+    #[test]
+    fn profiles() {
+        let profiles = ResourceProfiles(HashMap::from([(
+            "strict".to_string(),
+            ResourceLimits {
+                max_threads: Some(16),
+                max_open_files: Some(64),
+                ..Default::default()
+            },
+        )]));
+        let overrides = ResourceLimits {
+            max_open_files: Some(128),
+            ..Default::default()
+        };
+
+        let limits = profiles.resolve(Some("strict"), &overrides).unwrap();
+        assert_eq!(limits.max_threads, Some(16));
+        assert_eq!(limits.max_open_files, Some(128));
+
+        assert_eq!(profiles.resolve(None, &overrides).unwrap(), overrides);
+        assert!(profiles.resolve(Some("generous"), &overrides).is_err());
+    }
+
+    /// Set in the environment of the re-executed test binary, see [`thread_bomb`]
+    const THREAD_BOMB_ENV: &str = "KINCIR_THREAD_BOMB";
+
+    #[test]
+    fn thread_bomb() {
+        const BOMB: usize = 64;
+
+        if std::env::var_os(THREAD_BOMB_ENV).is_some() {
+            // the limited child: spawn threads until it fails
+            let mut threads = Vec::new();
+            while threads.len() < BOMB {
+                match std::thread::Builder::new()
+                    .spawn(|| std::thread::sleep(std::time::Duration::from_millis(500)))
+                {
+                    Ok(thread) => threads.push(thread),
+                    Err(_) => break,
+                }
+            }
+            println!("spawned {}", threads.len());
+            return;
+        }
+        if nix::unistd::geteuid().is_root() {
+            eprintln!("skipped: RLIMIT_NPROC isn't enforced for privileged users");
+            return;
+        }
+
+        let limits = ResourceLimits {
+            max_threads: Some(16),
+            ..Default::default()
+        };
+        // the test binary itself is the thread bomb
+        let mut command = std::process::Command::new(std::env::current_exe().unwrap());
+        command
+            .args(["--exact", "runner::limits::tests::thread_bomb"])
+            .args(["--nocapture", "--test-threads=1"])
+            .env(THREAD_BOMB_ENV, "1");
+        limits.install(&mut command);
+        let output = command.output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // printed right after the name of the test by the harness
+        let spawned = stdout
+            .split_once("spawned ")
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .unwrap_or_else(|| panic!("the thread bomb didn't run: {stdout}"));
+        // the other tasks of the user count too, so maybe less than the limit
+        assert!(spawned.parse::<usize>().unwrap() <= 16, "{spawned} threads");
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use super::{
+    distro_specific, libraries, limits::ResourceProfiles, Runner, RunnerEntryError, RunnerManifest,
+};
+
+/// The name of the manifest, inside the directory of each runner
+const MANIFEST_FILE_NAME: &str = "manifest.yml";
+
+/// Load every runner of `dir` (usually `./runners`), without any resource profile.
+///
+/// See [`load_runners_with_profiles`]
+pub(super) fn load_runners(dir: &Path) -> Result<Vec<Runner>, LoadError> {
+    load_runners_with_profiles(dir, &ResourceProfiles::default())
+}
+
+/// Load every runner of `dir` (usually `./runners`): each directory inside of it is a runner,
+/// described by its `manifest.yml`.
+///
+/// Every runner is loaded even if some of them are broken, the error then lists all of them: the
+/// service must refuse to start instead of running with some runners missing. The files that
+/// aren't directories are ignored
+pub(super) fn load_runners_with_profiles(
+    dir: &Path,
+    profiles: &ResourceProfiles,
+) -> Result<Vec<Runner>, LoadError> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| LoadError::Io(dir.to_path_buf(), e))? {
+        let path = entry
+            .map_err(|e| LoadError::Io(dir.to_path_buf(), e))?
+            .path();
+        if let (true, Some(name)) = (path.is_dir(), path.file_name()) {
+            names.push(name.to_os_string());
+        }
+    }
+    names.sort();
+
+    let mut runners = Vec::with_capacity(names.len());
+    let mut failures = Vec::new();
+    for name in names {
+        match load_runner(dir, &name, profiles) {
+            Ok(runner) => runners.push(runner),
+            Err(reason) => failures.push((dir.join(name), reason)),
+        }
+    }
+    if !failures.is_empty() {
+        return Err(LoadError::Invalid(failures));
+    }
+    Ok(runners)
+}
+
+/// Load the runner `<dir>/<name>`: parse its manifest and check it (see the `verify_*` methods
+/// of [`RunnerManifest`]), then let the handler of the host's distro add what the binaries need
+/// (see [`distro_specific::DISTRO_HANDLERS`]).
+///
+/// The problems that don't prevent the runner from working are only logged as warnings
+pub(super) fn load_runner(
+    dir: &Path,
+    name: &OsStr,
+    profiles: &ResourceProfiles,
+) -> Result<Runner, String> {
+    let path = dir.join(name).join(MANIFEST_FILE_NAME);
+    let manifest = std::fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    let mut manifest: RunnerManifest = serde_yaml::from_str(&manifest)
+        .map_err(|e| format!("failed to parse {}: {e}", path.display()))?;
+    if OsStr::new(&manifest.name) != name {
+        return Err(format!(
+            "the runner is named `{}` but its directory is `{}`",
+            manifest.name,
+            name.to_string_lossy()
+        ));
+    }
+    manifest.runners_dir = dir.to_path_buf();
+
+    // the errors borrow the manifest, which is moved into the runner afterwards
+    let error = |e: &dyn std::error::Error| e.to_string().trim_end().to_string();
+    let bin_deps = manifest.verify_bin_deps().map_err(|e| error(&e))?;
+    let file_deps = manifest.verify_files_deps().map_err(|e| error(&e))?;
+    manifest.check_files_deps_size().map_err(|e| error(&e))?;
+    manifest.verify_exit_status().map_err(|e| error(&e))?;
+    manifest.verify_env().map_err(|e| error(&e))?;
+    manifest.verify_write_quota().map_err(|e| error(&e))?;
+    match manifest.verify_entry() {
+        Err(e @ RunnerEntryError::InvalidPath(_)) => return Err(error(&e)),
+        Err(e) => log::warn!("runner `{}`: {}", manifest.name, error(&e)),
+        Ok(()) => {}
+    }
+    let limits = profiles
+        .resolve(manifest.resource_profile.as_deref(), &manifest.limits)
+        .map_err(|e| error(&e))?;
+
+    let mut runner = Runner {
+        id: uuid::Uuid::new_v4(),
+        manifest,
+        bin_deps,
+        file_deps,
+        absolute_file_deps: HashSet::new(),
+        limits,
+        on_complete: Vec::new(),
+    };
+    distro_specific::host_distro_handler()(&mut runner)
+        .map_err(|e| format!("failed to set up the runner for the distro: {e}"))?;
+    bind_shared_libraries(&mut runner, libraries::shared_libraries)?;
+    Ok(runner)
+}
+
+/// Add the shared libraries of the binary dependencies (found with `resolve`, see
+/// [`libraries::shared_libraries`]) to the paths bound into the sandbox.
+///
+/// The interpreter of each binary (see [`libraries::interpreter`]) is always added. If `ldd`
+/// isn't installed, the libraries aren't resolved and this is only a warning: the library
+/// directories bound by the distro handler are usually enough
+fn bind_shared_libraries(
+    runner: &mut Runner,
+    resolve: impl Fn(&Path, std::time::Duration) -> Result<Vec<PathBuf>, libraries::LibraryError>,
+) -> Result<(), String> {
+    let mut ldd_missing = false;
+    for (name, path) in runner.bin_deps.iter().sorted() {
+        // `ldd` lists it too, but this works even without `ldd`
+        match libraries::interpreter(path) {
+            Ok(interpreter) => runner.absolute_file_deps.extend(interpreter),
+            Err(e) => return Err(format!("binary dependency `{name}`: {e}")),
+        }
+        if ldd_missing {
+            continue;
+        }
+        match resolve(path, runner.manifest.setup_timeout) {
+            Ok(found) => runner.absolute_file_deps.extend(found),
+            Err(libraries::LibraryError::Ldd(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::warn!(
+                    "runner `{}`: ldd isn't installed, the shared libraries aren't resolved",
+                    runner.manifest.name
+                );
+                ldd_missing = true;
+            }
+            Err(e) => {
+                return Err(format!(
+                    "binary dependency `{name}`: {}",
+                    e.to_string().trim_end()
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The runners couldn't be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    /// The directory of the runners couldn't be read
+    Io(PathBuf, std::io::Error),
+    /// Some runners are broken: <runner_directory> -> <reason>
+    Invalid(Vec<(PathBuf, String)>),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(dir, e) => writeln!(f, "failed to read the runners in {}: {e}", dir.display()),
+            Self::Invalid(failures) => {
+                writeln!(f, "invalid runners")?;
+                for (dir, reason) in failures {
+                    writeln!(f, "- {}: {reason}", dir.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a runners directory holding a valid runner (`good`) and one with a missing files
+    /// dependency (`broken`)
+    fn fixture() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kincir-runners-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("good")).unwrap();
+        std::fs::write(
+            dir.join("good/manifest.yml"),
+            "name: good
+show_trace: true
+entry: entry.sh
+interpreter: sh
+bin_deps: [sh]
+no_default_binary: true
+files_deps:
+  entry.sh: entry.sh
+exit_status:
+  1: wrong answer
+timeout: 2
+",
+        )
+        .unwrap();
+        std::fs::write(dir.join("good/entry.sh"), "echo hello").unwrap();
+        std::fs::create_dir_all(dir.join("broken")).unwrap();
+        std::fs::write(
+            dir.join("broken/manifest.yml"),
+            "name: broken
+show_trace: false
+entry: entry.sh
+no_default_binary: true
+files_deps:
+  entry.sh: entry.sh
+",
+        )
+        .unwrap();
+        std::fs::write(dir.join("README"), "not a runner").unwrap();
+        dir
+    }
+
+    #[test]
+    fn load() {
+        let dir = fixture();
+        let good = load_runner(&dir, OsStr::new("good"), &ResourceProfiles::default());
+        let all = load_runners(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let good = good.unwrap();
+        assert_eq!(good.manifest.name, "good");
+        assert_eq!(good.manifest.timeout, std::time::Duration::from_secs(2));
+        assert_eq!(good.manifest.exit_status[&1], "wrong answer");
+        assert!(good.bin_deps.contains_key("sh"));
+        // the libraries of `sh`
+        assert!(good.absolute_file_deps.iter().any(|path| path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("libc."))));
+        assert_eq!(
+            good.file_deps.get(&dir.join("good/entry.sh")),
+            Some(&PathBuf::from("entry.sh"))
+        );
+
+        let Err(LoadError::Invalid(failures)) = all else {
+            panic!("the broken runner was loaded");
+        };
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, dir.join("broken"));
+        assert_eq!(failures[0].1, "missing path: entry.sh");
+    }
+
+    #[test]
+    fn name_mismatch() {
+        let dir = fixture();
+        std::fs::rename(dir.join("good"), dir.join("renamed")).unwrap();
+        let result = load_runner(&dir, OsStr::new("renamed"), &ResourceProfiles::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn ldd_missing() {
+        let dir = std::env::temp_dir().join(format!("kincir-runners-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("script"), "#!/bin/sh\n").unwrap();
+        let mut runner = super::super::tests::runner(super::super::tests::manifest("ldd"));
+        // sorted before `sh`, whose interpreter must still be found
+        runner.bin_deps.insert("a".to_string(), dir.join("script"));
+        runner
+            .bin_deps
+            .insert("sh".to_string(), PathBuf::from("/bin/sh"));
+        let calls = std::cell::Cell::new(0);
+        let result = bind_shared_libraries(&mut runner, |_, _| {
+            calls.set(calls.get() + 1);
+            Err(libraries::LibraryError::Ldd(
+                std::io::ErrorKind::NotFound.into(),
+            ))
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+        // warned only once
+        assert_eq!(calls.get(), 1);
+        let expected = libraries::interpreter(Path::new("/bin/sh")).unwrap();
+        assert!(expected.is_some());
+        assert_eq!(
+            runner.absolute_file_deps,
+            expected.into_iter().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn missing_dir() {
+        let dir = std::env::temp_dir().join(format!("kincir-runners-{}", uuid::Uuid::new_v4()));
+        assert!(matches!(load_runners(&dir), Err(LoadError::Io(..))));
+    }
+}
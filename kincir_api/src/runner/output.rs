@@ -0,0 +1,308 @@
+#[cfg(feature = "compression")]
+use std::io::Read;
+use std::{borrow::Cow, path::PathBuf, process::Stdio};
+
+use tokio::{
+    io::AsyncReadExt,
+    process::{ChildStderr, ChildStdout},
+};
+
+/// Where the stdout/stderr of a [`super::Run`] will be written
+#[derive(Debug, Clone, Default)]
+pub enum OutputDestination {
+    /// The output is buffered in memory, and given back as bytes in the [`super::RunOutput`].
+    ///
+    /// Only the first bytes of each stream are kept (see [`OutputDestination::capture`])
+    #[default]
+    Memory,
+
+    /// The output is written directly to the given files (which will be created or truncated)
+    /// while the run goes, and the [`super::RunOutput`] will only reference them.
+    ///
+    /// Use this for runs that could print a lot, since nothing is kept in memory. The files
+    /// aren't truncated, but their size is bounded by the `max_file_size` limit of the sandbox
+    /// since it is the sandbox that writes into them
+    Files { stdout: PathBuf, stderr: PathBuf },
+}
+
+/// The captured stdout/stderr of a run
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CapturedOutput {
+    /// The output was buffered in memory
+    Bytes(Vec<u8>),
+    /// The output was written to the given file
+    File(PathBuf),
+    /// The output was buffered in memory, and is stored gzip-compressed (see
+    /// [`CapturedOutput::compress`])
+    #[cfg(feature = "compression")]
+    Gzip(Vec<u8>),
+}
+
+/// How the captured bytes are turned into text.
+///
+/// In every case the raw bytes are kept, this only changes the text representation of them
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputEncoding {
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`
+    #[default]
+    Lossy,
+    /// Invalid UTF-8 bytes are escaped as `\xNN`, meaning that no information is lost
+    Escape,
+}
+
+impl OutputEncoding {
+    /// Convert the bytes into valid UTF-8
+    pub fn decode(self, bytes: &[u8]) -> Cow<'_, str> {
+        match self {
+            Self::Lossy => String::from_utf8_lossy(bytes),
+            Self::Escape => {
+                let mut out = String::with_capacity(bytes.len());
+                for chunk in bytes.utf8_chunks() {
+                    out.push_str(chunk.valid());
+                    for byte in chunk.invalid() {
+                        out.push_str(&format!("\\x{byte:02x}"));
+                    }
+                }
+                Cow::Owned(out)
+            }
+        }
+    }
+}
+
+impl CapturedOutput {
+    /// The captured bytes (decompressed if needed).
+    ///
+    /// This is `None` if the output was written to a file
+    pub fn bytes(&self) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Self::Bytes(bytes) => Some(Cow::Borrowed(bytes)),
+            Self::File(_) => None,
+            #[cfg(feature = "compression")]
+            Self::Gzip(compressed) => {
+                let mut bytes = Vec::new();
+                flate2::read::GzDecoder::new(compressed.as_slice())
+                    .read_to_end(&mut bytes)
+                    .ok()?;
+                Some(Cow::Owned(bytes))
+            }
+        }
+    }
+
+    /// The captured output as valid UTF-8.
+    ///
+    /// This is `None` if the output was written to a file
+    pub fn text(&self, encoding: OutputEncoding) -> Option<Cow<'_, str>> {
+        match self.bytes()? {
+            Cow::Borrowed(bytes) => Some(encoding.decode(bytes)),
+            Cow::Owned(bytes) => Some(Cow::Owned(encoding.decode(&bytes).into_owned())),
+        }
+    }
+
+    /// Compress the output buffered in memory, the outputs written to files are left as-is
+    #[cfg(feature = "compression")]
+    #[must_use]
+    pub fn compress(self) -> Self {
+        use std::io::Write;
+
+        let Self::Bytes(bytes) = self else {
+            return self;
+        };
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        // writing into a `Vec` can't fail
+        encoder.write_all(&bytes).unwrap();
+        Self::Gzip(encoder.finish().unwrap())
+    }
+}
+
+impl OutputDestination {
+    /// Create the handles that will be given to the sandbox as its stdout and stderr
+    pub fn stdio(&self) -> std::io::Result<(Stdio, Stdio)> {
+        match self {
+            Self::Memory => Ok((Stdio::piped(), Stdio::piped())),
+            Self::Files { stdout, stderr } => Ok((
+                std::fs::File::create(stdout)?.into(),
+                std::fs::File::create(stderr)?.into(),
+            )),
+        }
+    }
+
+    /// Capture the output of the sandbox, returning whether one of them was truncated.
+    ///
+    /// `stdout` and `stderr` are the pipes taken from the child (they'll be `None` when writing to
+    /// files). This must be called before waiting on the child, otherwise it could block on a full
+    /// pipe.
+    ///
+    /// In memory, only the first `max_bytes` bytes of each stream are kept: the rest is read and
+    /// discarded as it comes, so that the memory stays bounded while the sandbox doesn't block on
+    /// a full pipe
+    pub async fn capture(
+        &self,
+        stdout: Option<ChildStdout>,
+        stderr: Option<ChildStderr>,
+        max_bytes: u64,
+    ) -> std::io::Result<(CapturedOutput, CapturedOutput, bool)> {
+        match self {
+            Self::Memory => {
+                let ((stdout, stdout_truncated), (stderr, stderr_truncated)) = tokio::try_join!(
+                    read_bounded(stdout, max_bytes),
+                    read_bounded(stderr, max_bytes)
+                )?;
+                Ok((
+                    CapturedOutput::Bytes(stdout),
+                    CapturedOutput::Bytes(stderr),
+                    stdout_truncated || stderr_truncated,
+                ))
+            }
+            Self::Files { stdout, stderr } => Ok((
+                CapturedOutput::File(stdout.clone()),
+                CapturedOutput::File(stderr.clone()),
+                false,
+            )),
+        }
+    }
+}
+
+/// Read the whole pipe, keeping only its first `max_bytes` bytes. The returned boolean is
+/// whether something was discarded
+async fn read_bounded(
+    pipe: Option<impl AsyncReadExt + Unpin>,
+    max_bytes: u64,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut out = Vec::new();
+    let mut truncated = false;
+    let Some(mut pipe) = pipe else {
+        return Ok((out, truncated));
+    };
+    let mut chunk = [0; 8192];
+    loop {
+        let read = pipe.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok((out, truncated));
+        }
+        let room =
+            usize::try_from(max_bytes.saturating_sub(out.len() as u64)).unwrap_or(usize::MAX);
+        truncated |= read > room;
+        out.extend_from_slice(&chunk[..read.min(room)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run(destination: &OutputDestination) -> (CapturedOutput, CapturedOutput) {
+        let (stdout, stderr) = destination.stdio().unwrap();
+        let mut child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg("echo out; echo err >&2")
+            .stdout(stdout)
+            .stderr(stderr)
+            .spawn()
+            .unwrap();
+        let output = destination
+            .capture(child.stdout.take(), child.stderr.take(), u64::MAX)
+            .await
+            .unwrap();
+        child.wait().await.unwrap();
+        (output.0, output.1)
+    }
+
+    #[tokio::test]
+    async fn memory() {
+        let output = run(&OutputDestination::Memory).await;
+        assert_eq!(output.0, CapturedOutput::Bytes(b"out\n".to_vec()));
+        assert_eq!(output.1, CapturedOutput::Bytes(b"err\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8() {
+        let destination = OutputDestination::Memory;
+        let (stdout, stderr) = destination.stdio().unwrap();
+        let mut child = tokio::process::Command::new("printf")
+            .arg("ok \\377")
+            .stdout(stdout)
+            .stderr(stderr)
+            .spawn()
+            .unwrap();
+        let (stdout, _, _) = destination
+            .capture(child.stdout.take(), child.stderr.take(), u64::MAX)
+            .await
+            .unwrap();
+        child.wait().await.unwrap();
+        assert_eq!(stdout, CapturedOutput::Bytes(b"ok \xff".to_vec()));
+        assert_eq!(stdout.text(OutputEncoding::Lossy).unwrap(), "ok \u{fffd}");
+        assert_eq!(stdout.text(OutputEncoding::Escape).unwrap(), "ok \\xff");
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let capture = |script: &'static str| async move {
+            let destination = OutputDestination::Memory;
+            let (stdout, stderr) = destination.stdio().unwrap();
+            let mut child = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(script)
+                .stdout(stdout)
+                .stderr(stderr)
+                .spawn()
+                .unwrap();
+            let output = destination
+                .capture(child.stdout.take(), child.stderr.take(), 1024)
+                .await
+                .unwrap();
+            child.wait().await.unwrap();
+            output
+        };
+
+        // `yes` for a moment: far more than the limit, which must be drained without blocking
+        let (stdout, stderr, truncated) = capture("yes | head -c 10000000").await;
+        assert_eq!(stdout, CapturedOutput::Bytes(b"y\n".repeat(512)));
+        assert_eq!(stderr, CapturedOutput::Bytes(Vec::new()));
+        assert!(truncated);
+
+        let (stdout, _, truncated) = capture("head -c 1024 /dev/zero").await;
+        assert_eq!(stdout, CapturedOutput::Bytes(vec![0; 1024]));
+        assert!(!truncated);
+
+        let (_, stderr, truncated) = capture("head -c 1025 /dev/zero >&2").await;
+        assert_eq!(stderr, CapturedOutput::Bytes(vec![0; 1024]));
+        assert!(truncated);
+    }
+
+    #[tokio::test]
+    async fn files() {
+        let dir = std::env::temp_dir().join(format!("kincir-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let destination = OutputDestination::Files {
+            stdout: dir.join("stdout"),
+            stderr: dir.join("stderr"),
+        };
+        let output = run(&destination).await;
+        assert_eq!(output.0, CapturedOutput::File(dir.join("stdout")));
+        assert_eq!(output.1, CapturedOutput::File(dir.join("stderr")));
+        assert_eq!(std::fs::read(dir.join("stdout")).unwrap(), b"out\n");
+        assert_eq!(std::fs::read(dir.join("stderr")).unwrap(), b"err\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compression() {
+        let bytes = b"ok \xff ".repeat(1000);
+        let output = CapturedOutput::Bytes(bytes.clone()).compress();
+        let CapturedOutput::Gzip(compressed) = &output else {
+            panic!("the output should be compressed");
+        };
+        assert!(compressed.len() < bytes.len());
+        assert_eq!(output.bytes().unwrap(), bytes);
+        assert_eq!(
+            output.text(OutputEncoding::Escape).unwrap(),
+            "ok \\xff ".repeat(1000)
+        );
+
+        let file = CapturedOutput::File(PathBuf::from("stdout"));
+        assert_eq!(file.clone().compress(), file);
+    }
+}
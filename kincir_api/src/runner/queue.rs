@@ -0,0 +1,289 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::oneshot;
+
+/// Limit the number of [`super::Run`]s executing at the same time.
+///
+/// The runs that can't start right away wait for a slot, and when one is freed it is given to the
+/// waiting run with the highest priority (see [`super::Run::with_priority`]). Runs with the same
+/// priority start in the order they arrived.
+///
+/// The number of running and waiting runs can be read at any time (see [`RunQueue::running`] and
+/// [`RunQueue::waiting`]), as well as the position of a run in the queue (see
+/// [`QueuedRun::position`])
+#[derive(Debug)]
+pub struct RunQueue {
+    /// The maximum number of runs executing at the same time
+    limit: usize,
+    state: Mutex<QueueState>,
+}
+
+#[derive(Debug)]
+struct QueueState {
+    /// The number of free slots
+    available: usize,
+    /// The runs waiting for a slot
+    waiting: BinaryHeap<Waiter>,
+    /// Incremented for each waiter, used to keep the arrival order between equal priorities
+    next_seq: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    priority: i32,
+    seq: u64,
+    sender: oneshot::Sender<RunPermit>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // the heap pops the greatest first: highest priority, then the oldest
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// A slot of a [`RunQueue`], given back to the queue when dropped
+#[derive(Debug)]
+pub struct RunPermit {
+    queue: Option<Arc<RunQueue>>,
+}
+
+/// A run that entered a [`RunQueue`], see [`RunQueue::enqueue`].
+///
+/// Dropping it before it got its slot makes the run leave the queue
+#[derive(Debug)]
+pub struct QueuedRun {
+    queue: Arc<RunQueue>,
+    slot: Slot,
+}
+
+#[derive(Debug)]
+enum Slot {
+    /// A slot was free when the run entered the queue
+    Free(RunPermit),
+    /// The run waits, `seq` is the one of its [`Waiter`]
+    Waiting {
+        seq: u64,
+        receiver: oneshot::Receiver<RunPermit>,
+    },
+}
+
+impl QueuedRun {
+    /// The number of runs that will start before this one, or `None` if it already got its slot
+    pub fn position(&self) -> Option<usize> {
+        let Slot::Waiting { seq, .. } = &self.slot else {
+            return None;
+        };
+        let state = self.queue.state.lock().unwrap();
+        let this = state.waiting.iter().find(|waiter| waiter.seq == *seq)?;
+        let before = state
+            .waiting
+            .iter()
+            .filter(|waiter| *waiter > this && !waiter.sender.is_closed())
+            .count();
+        Some(before)
+    }
+
+    /// Wait for the slot of the run
+    pub async fn ready(self) -> RunPermit {
+        match self.slot {
+            Slot::Free(permit) => permit,
+            // the sender is only dropped after sending a permit
+            Slot::Waiting { receiver, .. } => receiver.await.expect("run queue dropped a waiter"),
+        }
+    }
+}
+
+impl RunQueue {
+    /// Create a queue letting at most `limit` runs execute at the same time
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            state: Mutex::new(QueueState {
+                available: limit,
+                waiting: BinaryHeap::new(),
+                next_seq: 0,
+            }),
+        })
+    }
+
+    /// Wait for a free slot.
+    ///
+    /// If the returned future is dropped while waiting, the run simply leaves the queue
+    pub async fn acquire(self: &Arc<Self>, priority: i32) -> RunPermit {
+        self.enqueue(priority).ready().await
+    }
+
+    /// Enter the queue, taking a free slot right away if there is one.
+    ///
+    /// Unlike [`RunQueue::acquire`], the position of the run can be followed while it waits
+    pub fn enqueue(self: &Arc<Self>, priority: i32) -> QueuedRun {
+        let mut state = self.state.lock().unwrap();
+        let slot = if state.available > 0 && state.waiting.is_empty() {
+            state.available -= 1;
+            Slot::Free(RunPermit {
+                queue: Some(self.clone()),
+            })
+        } else {
+            let (sender, receiver) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiting.push(Waiter {
+                priority,
+                seq,
+                sender,
+            });
+            Slot::Waiting { seq, receiver }
+        };
+        QueuedRun {
+            queue: self.clone(),
+            slot,
+        }
+    }
+
+    /// The maximum number of runs executing at the same time
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The number of runs holding a slot
+    pub fn running(&self) -> usize {
+        self.limit - self.state.lock().unwrap().available
+    }
+
+    /// The number of runs waiting for a slot
+    pub fn waiting(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .waiting
+            .iter()
+            .filter(|waiter| !waiter.sender.is_closed())
+            .count()
+    }
+
+    /// Give a freed slot to the next waiting run, or make it available
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(waiter) = state.waiting.pop() {
+            let permit = RunPermit {
+                queue: Some(self.clone()),
+            };
+            match waiter.sender.send(permit) {
+                Ok(()) => return,
+                // the waiter left the queue, the permit must not be released a second time
+                Err(mut permit) => {
+                    permit.queue = None;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+impl Drop for RunPermit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wait until `count` runs are waiting in the queue
+    async fn wait_for(queue: &RunQueue, count: usize) {
+        while queue.waiting() != count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn priority() {
+        let queue = RunQueue::new(1);
+        let first = queue.acquire(0).await;
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        for (count, priority) in [(1, 0), (2, 10), (3, 0)] {
+            let sender = sender.clone();
+            tokio::spawn({
+                let queue = queue.clone();
+                async move {
+                    let _permit = queue.acquire(priority).await;
+                    sender.send((priority, count)).unwrap();
+                }
+            });
+            wait_for(&queue, count).await;
+        }
+        drop(first);
+
+        let mut order = Vec::new();
+        for _ in 0..3 {
+            order.push(receiver.recv().await.unwrap());
+        }
+        assert_eq!(order, [(10, 2), (0, 1), (0, 3)]);
+    }
+
+    #[tokio::test]
+    async fn limit() {
+        let queue = RunQueue::new(2);
+        let first = queue.acquire(0).await;
+        let _second = queue.acquire(0).await;
+        assert_eq!((queue.running(), queue.waiting()), (2, 0));
+
+        let third = queue.enqueue(0);
+        let fourth = queue.enqueue(0);
+        assert_eq!(third.position(), Some(0));
+        assert_eq!(fourth.position(), Some(1));
+        assert_eq!((queue.running(), queue.waiting()), (2, 2));
+        let third = tokio::spawn(third.ready());
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!third.is_finished());
+
+        drop(first);
+        let _third = third.await.unwrap();
+        assert_eq!(fourth.position(), Some(0));
+        assert_eq!((queue.running(), queue.waiting()), (2, 1));
+
+        let fifth = RunQueue::new(1).enqueue(0);
+        assert_eq!(fifth.position(), None);
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter() {
+        let queue = RunQueue::new(1);
+        let first = queue.acquire(0).await;
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(0).await }
+        });
+        wait_for(&queue, 1).await;
+        waiter.abort();
+        let _ = waiter.await;
+        assert_eq!(queue.waiting(), 0);
+        drop(first);
+
+        let _second = queue.acquire(0).await;
+    }
+}
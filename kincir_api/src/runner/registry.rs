@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use super::{Run, Runner};
+
+/// Every loaded [`Runner`], indexed both by the name of its manifest and by its id
+#[derive(Debug, Default)]
+pub struct RunnerRegistry {
+    /// The runners: <id> -> <runner>
+    runners: HashMap<uuid::Uuid, Runner>,
+    /// The id of each runner: <name> -> <id>
+    names: HashMap<String, uuid::Uuid>,
+}
+
+impl RunnerRegistry {
+    /// Create a registry holding the given runners (usually the ones returned by
+    /// [`super::loader::load_runners`]).
+    ///
+    /// It is an error to have two runners with the same name
+    pub fn new(runners: impl IntoIterator<Item = Runner>) -> Result<Self, RunnerRegistryError> {
+        let mut registry = Self::default();
+        for runner in runners {
+            registry.insert(runner)?;
+        }
+        Ok(registry)
+    }
+
+    /// Add a runner to the registry, failing if a runner with the same name is already present
+    pub fn insert(&mut self, runner: Runner) -> Result<(), RunnerRegistryError> {
+        if self.names.contains_key(&runner.manifest.name) {
+            return Err(RunnerRegistryError::DuplicateName(
+                runner.manifest.name.clone(),
+            ));
+        }
+        self.names.insert(runner.manifest.name.clone(), runner.id);
+        self.runners.insert(runner.id, runner);
+        Ok(())
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Result<&Runner, RunnerRegistryError> {
+        self.names
+            .get(name)
+            .and_then(|id| self.runners.get(id))
+            .ok_or_else(|| RunnerRegistryError::UnknownName(name.to_string()))
+    }
+
+    pub fn get_by_id(&self, id: uuid::Uuid) -> Result<&Runner, RunnerRegistryError> {
+        self.runners
+            .get(&id)
+            .ok_or(RunnerRegistryError::UnknownId(id))
+    }
+
+    /// Same as [`RunnerRegistry::get_by_id`], for example to register callbacks with
+    /// [`Runner::on_complete`]
+    pub fn get_by_id_mut(&mut self, id: uuid::Uuid) -> Result<&mut Runner, RunnerRegistryError> {
+        self.runners
+            .get_mut(&id)
+            .ok_or(RunnerRegistryError::UnknownId(id))
+    }
+
+    /// The runner that the given [`Run`] belongs to
+    pub fn get_for_run(&self, run: &Run) -> Result<&Runner, RunnerRegistryError> {
+        self.get_by_id(run.runner_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Runner> {
+        self.runners.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.runners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runners.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunnerRegistryError {
+    DuplicateName(String),
+    UnknownName(String),
+    UnknownId(uuid::Uuid),
+}
+
+impl std::fmt::Display for RunnerRegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DuplicateName(name) => writeln!(f, "duplicate runner `{name}`"),
+            Self::UnknownName(name) => writeln!(f, "unknown runner `{name}`"),
+            Self::UnknownId(id) => writeln!(f, "unknown runner id {id}"),
+        }
+    }
+}
+
+impl std::error::Error for RunnerRegistryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::{manifest, runner};
+
+    #[test]
+    fn lookup() {
+        let first = runner(manifest("first"));
+        let second = runner(manifest("second"));
+        let (first_id, second_id) = (first.id, second.id);
+        let registry = RunnerRegistry::new([first, second]).unwrap();
+        assert_eq!(registry.len(), 2);
+
+        assert_eq!(registry.get_by_name("first").unwrap().id, first_id);
+        assert_eq!(
+            registry.get_by_id(second_id).unwrap().manifest.name,
+            "second"
+        );
+        let run = Run::new(registry.get_by_name("second").unwrap());
+        assert_eq!(registry.get_for_run(&run).unwrap().id, second_id);
+
+        assert_eq!(
+            registry.get_by_name("third").unwrap_err(),
+            RunnerRegistryError::UnknownName("third".to_string())
+        );
+        let unknown = uuid::Uuid::new_v4();
+        assert_eq!(
+            registry.get_by_id(unknown).unwrap_err(),
+            RunnerRegistryError::UnknownId(unknown)
+        );
+    }
+
+    #[test]
+    fn duplicate_name() {
+        let result = RunnerRegistry::new([runner(manifest("same")), runner(manifest("same"))]);
+        assert_eq!(
+            result.unwrap_err(),
+            RunnerRegistryError::DuplicateName("same".to_string())
+        );
+    }
+}
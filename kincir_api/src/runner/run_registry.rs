@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio::{sync::RwLock, time::Instant};
+
+use super::{CancelHandle, Run};
+
+/// A [`Run`] shared between the tasks: the one waiting for its completion, and the ones looking
+/// at its state
+pub type SharedRun = Arc<RwLock<Run>>;
+
+/// Every [`Run`] that is still running or that was over recently, indexed by its id.
+///
+/// The runs that are over are kept for the retention duration given at creation, so that their
+/// output can still be fetched, and are removed by [`RunRegistry::prune`] afterwards.
+///
+/// Each run has its own lock: waiting for the completion of a run (which needs it mutably) doesn't
+/// block the access to the other runs
+#[derive(Debug)]
+pub struct RunRegistry {
+    runs: RwLock<HashMap<uuid::Uuid, Entry>>,
+    /// How long the runs that are over are kept
+    retention: Duration,
+}
+
+/// A run of the registry, with its cancel handle that must stay usable while the run is locked
+#[derive(Debug)]
+struct Entry {
+    run: SharedRun,
+    cancel: CancelHandle,
+}
+
+impl RunRegistry {
+    pub fn new(retention: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            runs: RwLock::new(HashMap::new()),
+            retention,
+        })
+    }
+
+    /// Add a run to the registry, returning the shared handle used to access it afterwards
+    pub async fn insert(&self, run: Run) -> SharedRun {
+        let id = run.id;
+        let cancel = run.cancel_handle();
+        let run = Arc::new(RwLock::new(run));
+        self.runs.write().await.insert(
+            id,
+            Entry {
+                run: run.clone(),
+                cancel,
+            },
+        );
+        run
+    }
+
+    /// The run with the given id, `None` if it doesn't exist or was pruned
+    pub async fn get(&self, id: uuid::Uuid) -> Option<SharedRun> {
+        self.runs
+            .read()
+            .await
+            .get(&id)
+            .map(|entry| entry.run.clone())
+    }
+
+    pub async fn remove(&self, id: uuid::Uuid) -> Option<SharedRun> {
+        self.runs.write().await.remove(&id).map(|entry| entry.run)
+    }
+
+    /// Cancel the run with the given id (see [`CancelHandle::cancel`]), returning `false` if it
+    /// doesn't exist.
+    ///
+    /// This doesn't need to lock the run, meaning that it works while the run is being awaited
+    pub async fn cancel(&self, id: uuid::Uuid) -> bool {
+        match self.runs.read().await.get(&id) {
+            Some(entry) => {
+                entry.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn len(&self) -> usize {
+        self.runs.read().await.len()
+    }
+
+    /// Remove the runs that were over for longer than the retention, returning how many were
+    /// removed.
+    ///
+    /// A run that is currently locked (for reading or writing) is kept: somebody is still using it
+    pub async fn prune(&self) -> usize {
+        let now = Instant::now();
+        let mut runs = self.runs.write().await;
+        let before = runs.len();
+        runs.retain(|_, Entry { run, .. }| {
+            // `try_write` fails if the run is accessed by anyone, even only to read it
+            let Ok(run) = run.try_write() else {
+                return true;
+            };
+            run.finished_at
+                .is_none_or(|finished_at| now.duration_since(finished_at) < self.retention)
+        });
+        before - runs.len()
+    }
+
+    /// Spawn a task calling [`RunRegistry::prune`] every `period`.
+    ///
+    /// The task stops by itself once the registry is dropped
+    pub fn prune_every(self: &Arc<Self>, period: Duration) -> tokio::task::JoinHandle<()> {
+        let registry: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let Some(registry) = registry.upgrade() else {
+                    return;
+                };
+                let pruned = registry.prune().await;
+                if pruned != 0 {
+                    log::debug!("pruned {pruned} runs");
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::{manifest, runner};
+    use crate::runner::RunState;
+
+    #[tokio::test]
+    async fn insert_and_get() {
+        let runner = runner(manifest("registry"));
+        let registry = RunRegistry::new(Duration::from_secs(60));
+        let run = Run::new(&runner);
+        let id = run.id;
+
+        let shared = registry.insert(run).await;
+        let found = registry.get(id).await.unwrap();
+        assert!(Arc::ptr_eq(&shared, &found));
+        assert_eq!(found.read().await.id, id);
+        assert!(registry.get(uuid::Uuid::new_v4()).await.is_none());
+
+        assert!(registry.remove(id).await.is_some());
+        assert!(registry.get(id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let runner = runner(manifest("registry_cancel"));
+        let registry = RunRegistry::new(Duration::from_secs(60));
+        let mut run = Run::new(&runner);
+        let mut command = std::process::Command::new("sleep");
+        command.arg("30");
+        run.spawn(command).unwrap();
+        let id = run.id;
+        let shared = registry.insert(run).await;
+
+        let waiting = tokio::spawn(async move {
+            let mut run = shared.write().await;
+            run.await_completion(&runner).await.unwrap();
+            matches!(run.state, RunState::Cancelled)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(registry.cancel(id).await);
+        assert!(!registry.cancel(uuid::Uuid::new_v4()).await);
+        let cancelled = tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(cancelled);
+    }
+
+    #[tokio::test]
+    async fn retention() {
+        let runner = runner(manifest("retention"));
+        let registry = RunRegistry::new(Duration::from_millis(50));
+        let running = registry.insert(Run::new(&runner)).await;
+        let finished = registry.insert(Run::new(&runner)).await;
+        let locked = registry.insert(Run::new(&runner)).await;
+        finished.write().await.finish(&runner, RunState::TimedOut);
+        locked.write().await.finish(&runner, RunState::TimedOut);
+
+        assert_eq!(registry.prune().await, 0);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let guard = locked.read().await;
+        assert_eq!(registry.prune().await, 1);
+        drop(guard);
+
+        let (running, finished) = (running.read().await.id, finished.read().await.id);
+        assert!(registry.get(running).await.is_some());
+        assert!(registry.get(finished).await.is_none());
+        assert_eq!(registry.len().await, 2);
+    }
+
+    #[tokio::test]
+    async fn prune_every() {
+        let runner = runner(manifest("prune_every"));
+        let registry = RunRegistry::new(Duration::ZERO);
+        let finished = registry.insert(Run::new(&runner)).await;
+        finished.write().await.finish(&runner, RunState::TimedOut);
+        drop(finished);
+
+        let task = registry.prune_every(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(registry.len().await, 0);
+        drop(registry);
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
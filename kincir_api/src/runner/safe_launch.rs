@@ -0,0 +1,69 @@
+use std::os::unix::fs::PermissionsExt;
+
+use kincir_bwrap::BwrapCommand;
+
+use super::scratch::ScratchDir;
+
+/// Where the wrapper is available inside the sandbox
+pub const PATH: &str = "/bin/safe-launch";
+
+/// The variables removed by the wrapper, since they give away the private paths of the runner
+pub const HIDDEN_VARIABLES: &[&str] = &["FILES_ROOT", "SUBMITTED_ROOT", "TRACE_FILE"];
+
+/// The `safe-launch` wrapper: it runs its arguments as a command, without the
+/// [`HIDDEN_VARIABLES`].
+///
+/// This is a bash script, meaning that `bash` must be one of the binary dependencies (which it is
+/// unless [`super::RunnerManifest::no_default_binary`] is set)
+fn script() -> String {
+    format!(
+        "#!/bin/bash\nunset {}\nexec \"$@\"\n",
+        HIDDEN_VARIABLES.join(" ")
+    )
+}
+
+/// Write the wrapper in a new directory of the host, and bind it read-only at [`PATH`] inside
+/// the sandbox.
+///
+/// The wrapper is removed with the returned directory, which must be kept for as long as the
+/// sandbox runs
+pub fn install(command: &mut BwrapCommand<'_>) -> std::io::Result<ScratchDir> {
+    let dir = ScratchDir::create(PATH)?;
+    let host = dir.host().join("safe-launch");
+    std::fs::write(&host, script())?;
+    std::fs::set_permissions(&host, std::fs::Permissions::from_mode(0o755))?;
+    command.bind_read_only(host, dir.guest());
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_variables() {
+        let mut command = BwrapCommand::new("ls");
+        let dir = install(&mut command).unwrap();
+        let host = dir.host().join("safe-launch");
+        let args = command.build_args().unwrap();
+        assert_eq!(
+            args[..3],
+            ["--ro-bind".as_ref(), host.as_os_str(), PATH.as_ref()]
+        );
+
+        let output = std::process::Command::new(&host)
+            .arg("env")
+            .env("FILES_ROOT", "/files")
+            .env("SUBMITTED_ROOT", "/submitted")
+            .env("TRACE_FILE", "/trace/trace")
+            .env("KEPT", "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let env = String::from_utf8(output.stdout).unwrap();
+        assert!(env.lines().any(|line| line == "KEPT=1"));
+        for variable in HIDDEN_VARIABLES {
+            assert!(!env.contains(variable), "{variable} leaked");
+        }
+    }
+}
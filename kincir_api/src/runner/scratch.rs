@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+
+use kincir_bwrap::BwrapCommand;
+
+/// A writable directory shared by every step of a single [`super::Run`].
+///
+/// It is created empty for each run, and removed (with its content) when dropped, see
+/// [`remove_tree`]
+#[derive(Debug)]
+pub struct ScratchDir {
+    /// Where the directory lives on the host
+    host: PathBuf,
+    /// Where the directory will be mounted inside the sandbox
+    guest: PathBuf,
+}
+
+impl ScratchDir {
+    /// Create a new empty scratch directory (in the host's temporary directory) that will be
+    /// mounted at `guest`
+    pub fn create(guest: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let host = std::env::temp_dir().join(format!("kincir-scratch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&host)?;
+        Ok(Self {
+            host,
+            guest: guest.into(),
+        })
+    }
+
+    pub fn host(&self) -> &Path {
+        &self.host
+    }
+
+    pub fn guest(&self) -> &Path {
+        &self.guest
+    }
+
+    /// Bind the scratch directory into the given sandbox
+    pub fn bind(&self, command: &mut BwrapCommand<'_>) {
+        command.bind(&self.host, &self.guest);
+    }
+}
+
+/// Remove the directory `path` with its content, without ever following a symlink: a symlink
+/// (including `path` itself) is removed, not what it points to.
+///
+/// The content was possibly written by the sandbox, which could have removed the write permission
+/// of some directories: these are made writable again by the owner before retrying
+fn remove_tree(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match std::fs::remove_dir_all(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {}
+        result => return result,
+    }
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        // `symlink_metadata` doesn't follow the symlinks, which are left untouched
+        if !std::fs::symlink_metadata(&dir)?.is_dir() {
+            continue;
+        }
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+        for entry in std::fs::read_dir(&dir)? {
+            dirs.push(entry?.path());
+        }
+    }
+    std::fs::remove_dir_all(path)
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if let Err(e) = remove_tree(&self.host) {
+            log::warn!(
+                "failed to remove scratch directory {}: {e}",
+                self.host.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_tree() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let outside = ScratchDir::create("/outside").unwrap();
+        std::fs::write(outside.host().join("kept"), "").unwrap();
+
+        let scratch = ScratchDir::create("/scratch").unwrap();
+        let host = scratch.host().to_path_buf();
+        std::os::unix::fs::symlink(outside.host(), host.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.host().join("kept"), host.join("file")).unwrap();
+        std::fs::create_dir_all(host.join("locked/inner")).unwrap();
+        std::fs::write(host.join("locked/inner/file"), "").unwrap();
+        for dir in ["locked/inner", "locked"] {
+            std::fs::set_permissions(host.join(dir), std::fs::Permissions::from_mode(0o500))
+                .unwrap();
+        }
+        drop(scratch);
+
+        assert!(!host.exists());
+        assert!(outside.host().join("kept").exists());
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::BTreeMap,
+    path::{Component, PathBuf},
+};
+
+use kincir_bwrap::BwrapCommand;
+use tokio::{io::AsyncWriteExt, process::ChildStdin};
+
+use super::scratch::ScratchDir;
+
+/// The files submitted by the user for a single [`super::Run`].
+///
+/// They are written to a new directory for each run, mounted read-only at `SUBMITTED_ROOT` inside
+/// the sandbox. Their content is NOT trusted, only their paths and total size are checked (see
+/// [`RunRequest::verify`])
+#[derive(Debug, Default, Clone)]
+pub struct RunRequest {
+    /// The submitted files: <relative_path> -> <content>
+    files: BTreeMap<PathBuf, Vec<u8>>,
+
+    /// What is written to the stdin of the sandbox, which reads an empty stdin if there is none
+    stdin: Option<RunStdin>,
+}
+
+/// The input given to the stdin of the sandbox (see [`RunRequest::with_stdin`]).
+///
+/// It is written while the run goes, and the stdin is closed once everything is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunStdin {
+    /// The input is given as bytes
+    Bytes(Vec<u8>),
+
+    /// The input is streamed from the given file of the host, which is only opened once the run
+    /// is launched.
+    ///
+    /// Use this for large inputs, since the file is never fully read in memory
+    File(PathBuf),
+}
+
+impl RunStdin {
+    /// Write the input into the stdin `pipe` of the sandbox, and close it afterward.
+    ///
+    /// The sandbox is free to exit without reading all of its input: the input left is then
+    /// silently discarded
+    pub(super) async fn feed(&self, pipe: Option<ChildStdin>) -> std::io::Result<()> {
+        let Some(mut pipe) = pipe else {
+            return Ok(());
+        };
+        let written = match self {
+            Self::Bytes(bytes) => pipe.write_all(bytes).await,
+            Self::File(path) => {
+                let mut file = tokio::fs::File::open(path).await?;
+                tokio::io::copy(&mut file, &mut pipe).await.map(|_| ())
+            }
+        };
+        match written {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+            written => written,
+        }
+    }
+}
+
+impl RunRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a submitted file, at `path` relative to `SUBMITTED_ROOT`.
+    ///
+    /// A file given twice keeps the last content. The parent directories are created when the
+    /// files are written
+    pub fn with_file(
+        &mut self,
+        path: impl Into<PathBuf>,
+        content: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.files.insert(path.into(), content.into());
+        self
+    }
+
+    /// Write `stdin` to the stdin of the sandbox. A later call replaces the previous input
+    pub fn with_stdin(&mut self, stdin: RunStdin) -> &mut Self {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    /// The input given to the stdin of the sandbox, if any
+    pub fn stdin(&self) -> Option<&RunStdin> {
+        self.stdin.as_ref()
+    }
+
+    /// The total size (in bytes) of the submitted files, not counting the stdin
+    pub fn size(&self) -> u64 {
+        self.files
+            .values()
+            .map(|content| content.len() as u64)
+            .sum()
+    }
+
+    /// Check that every path stays inside of `SUBMITTED_ROOT` (no absolute path, no `..`), and
+    /// that the total size is at most `max_size` bytes
+    pub fn verify(&self, max_size: u64) -> Result<(), SubmissionError> {
+        for path in self.files.keys() {
+            let mut components = path.components().peekable();
+            if components.peek().is_none() || !components.all(|c| matches!(c, Component::Normal(_)))
+            {
+                return Err(SubmissionError::InvalidPath(path.clone()));
+            }
+        }
+        let size = self.size();
+        if size > max_size {
+            return Err(SubmissionError::TooLarge {
+                size,
+                max: max_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the submitted files into a new directory of the host, and bind it read-only at
+    /// `guest` inside the sandbox.
+    ///
+    /// The files are removed with the returned directory, which must be kept for as long as the
+    /// sandbox runs. The request must have been checked with [`RunRequest::verify`] beforehand
+    pub fn install(
+        &self,
+        command: &mut BwrapCommand<'_>,
+        guest: impl Into<PathBuf>,
+    ) -> std::io::Result<ScratchDir> {
+        let dir = ScratchDir::create(guest)?;
+        for (path, content) in &self.files {
+            let host = dir.host().join(path);
+            if let Some(parent) = host.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(host, content)?;
+        }
+        command.bind_read_only(dir.host(), dir.guest());
+        Ok(dir)
+    }
+}
+
+#[derive(Debug)]
+pub enum SubmissionError {
+    InvalidPath(PathBuf),
+    TooLarge { size: u64, max: u64 },
+}
+
+impl std::fmt::Display for SubmissionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidPath(p) => writeln!(f, "invalid submitted path {}", p.display()),
+            Self::TooLarge { size, max } => writeln!(
+                f,
+                "submitted files are too large: {size} bytes (maximum is {max} bytes)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SubmissionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn verify() {
+        let mut request = RunRequest::new();
+        request
+            .with_file("main.c", "int main() {}")
+            .with_file("src/lib.c", "");
+        assert!(request.verify(13).is_ok());
+        assert!(matches!(
+            request.verify(12),
+            Err(SubmissionError::TooLarge { size: 13, max: 12 })
+        ));
+
+        for path in [
+            "/etc/passwd",
+            "../escape",
+            "src/../../escape",
+            "./main.c",
+            "",
+        ] {
+            let mut request = RunRequest::new();
+            request.with_file(path, "");
+            assert!(
+                matches!(request.verify(u64::MAX), Err(SubmissionError::InvalidPath(p)) if p == Path::new(path)),
+                "{path} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn install() {
+        let mut request = RunRequest::new();
+        request
+            .with_file("main.c", "int main() {}")
+            .with_file("src/lib.c", "int lib;");
+        let mut command = BwrapCommand::new("ls");
+        let dir = request.install(&mut command, "/submitted").unwrap();
+        assert_eq!(
+            std::fs::read(dir.host().join("main.c")).unwrap(),
+            b"int main() {}"
+        );
+        assert_eq!(
+            std::fs::read(dir.host().join("src/lib.c")).unwrap(),
+            b"int lib;"
+        );
+        let args = command.build_args().unwrap();
+        assert_eq!(
+            args[..3],
+            [
+                "--ro-bind".as_ref(),
+                dir.host().as_os_str(),
+                "/submitted".as_ref()
+            ]
+        );
+
+        let host = dir.host().to_path_buf();
+        drop(dir);
+        assert!(!host.exists());
+    }
+}
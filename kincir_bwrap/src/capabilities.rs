@@ -0,0 +1,171 @@
+use std::ffi::OsStr;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// The linux capabilities, indexed by their number
+const CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// The mask of the given capabilities, or `None` if one of them is unknown.
+///
+/// The names are case insensitive, the `CAP_` prefix is optional and `ALL` means every
+/// capability
+fn capabilities_mask<S: AsRef<OsStr>>(caps: impl IntoIterator<Item = S>) -> Option<u64> {
+    let mut mask = 0u64;
+    for cap in caps {
+        let name = cap.as_ref().to_str()?.to_ascii_uppercase();
+        if name == "ALL" {
+            mask |= (1 << CAPABILITIES.len()) - 1;
+            continue;
+        }
+        let name = if name.starts_with("CAP_") {
+            name
+        } else {
+            format!("CAP_{name}")
+        };
+        mask |= 1 << CAPABILITIES.iter().position(|&c| c == name)?;
+    }
+    Some(mask)
+}
+
+/// Find the value of a field of `/proc/<pid>/status`
+fn status_field<'a>(status: &'a str, field: &str) -> Option<&'a str> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .map(str::trim)
+}
+
+/// Check if bwrap, executed by a process described by `status` (the content of
+/// `/proc/<pid>/status`), is allowed to add the capabilities of `mask` to the sandbox.
+///
+/// A setuid bwrap only accepts `--cap-add` from a caller whose real uid is root. Otherwise the
+/// sandbox gets every capability inside of a new user namespace (`unshares_user`), or the caller
+/// must keep the capabilities when executing bwrap
+fn status_permits(status: &str, bwrap_is_setuid: bool, unshares_user: bool, mask: u64) -> bool {
+    let uid = |index| {
+        status_field(status, "Uid")
+            .and_then(|uids| uids.split_whitespace().nth(index))
+            .is_some_and(|uid| uid == "0")
+    };
+    if bwrap_is_setuid {
+        return uid(0);
+    }
+    if unshares_user {
+        return true;
+    }
+    let is_root = uid(1);
+    // an unprivileged user loses its capabilities when executing bwrap, except the ambient ones
+    let field = if is_root { "CapEff" } else { "CapAmb" };
+    status_field(status, field)
+        .and_then(|caps| u64::from_str_radix(caps, 16).ok())
+        .is_some_and(|caps| caps & mask == mask)
+}
+
+/// Check if bwrap will be able to add the given capabilities (with `--cap-add`) to the sandbox.
+///
+/// See [`status_permits`] for the rules, `unshares_user` being whether the sandbox is in a new
+/// user namespace
+pub(crate) fn can_add_capabilities<S: AsRef<OsStr>>(
+    bwrap: &OsStr,
+    unshares_user: bool,
+    caps: impl IntoIterator<Item = S>,
+) -> bool {
+    let Some(mask) = capabilities_mask(caps) else {
+        return false;
+    };
+    let bwrap_is_setuid = find_program(bwrap)
+        .and_then(|path| path.metadata().ok())
+        .is_some_and(|m| m.uid() == 0 && m.permissions().mode() & 0o4000 != 0);
+    std::fs::read_to_string("/proc/self/status")
+        .is_ok_and(|status| status_permits(&status, bwrap_is_setuid, unshares_user, mask))
+}
+
+/// Find the path of `program` like a shell would (using the `PATH` if it isn't a path)
+fn find_program(program: &OsStr) -> Option<PathBuf> {
+    if Path::new(program).components().count() > 1 {
+        return Some(PathBuf::from(program));
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_STATUS: &str = "Name:\tcat\nUid:\t1000\t1000\t1000\t1000\nCapInh:\t0000000000000000\nCapPrm:\t0000000000000000\nCapEff:\t0000000000000000\nCapAmb:\t0000000000000000\n";
+    const ROOT_STATUS: &str = "Name:\tcat\nUid:\t0\t0\t0\t0\nCapInh:\t0000000000000000\nCapPrm:\t000001ffffffffff\nCapEff:\t000001ffffffffff\nCapAmb:\t0000000000000000\n";
+
+    #[test]
+    fn mask() {
+        assert_eq!(capabilities_mask(["CAP_CHOWN"]), Some(1));
+        assert_eq!(
+            capabilities_mask(["net_raw", "CAP_KILL"]),
+            Some(1 << 13 | 1 << 5)
+        );
+        assert_eq!(capabilities_mask(["ALL"]), Some(0x1ff_ffff_ffff));
+        assert_eq!(capabilities_mask(["CAP_UNKNOWN"]), None);
+    }
+
+    #[test]
+    fn unprivileged() {
+        let mask = capabilities_mask(["CAP_NET_ADMIN"]).unwrap();
+        assert!(!status_permits(USER_STATUS, false, false, mask));
+        assert!(status_permits(USER_STATUS, false, true, mask));
+        assert!(status_permits(ROOT_STATUS, false, false, mask));
+    }
+
+    #[test]
+    fn setuid() {
+        let mask = capabilities_mask(["CAP_NET_ADMIN"]).unwrap();
+        assert!(!status_permits(USER_STATUS, true, false, mask));
+        assert!(!status_permits(USER_STATUS, true, true, mask));
+        assert!(status_permits(ROOT_STATUS, true, false, mask));
+        // only the real uid counts, not the effective one
+        let setuid_status = USER_STATUS.replace("Uid:\t1000\t1000", "Uid:\t1000\t0");
+        assert!(!status_permits(&setuid_status, true, false, mask));
+    }
+}
@@ -15,12 +15,23 @@ use std::{os::unix::ffi::OsStrExt, process::Stdio};
 /// Command::new("sh").arg("-c").arg("echo hello");
 /// ```
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Command {
     pub(crate) program: OsString,
     pub(crate) args: Vec<OsString>,
-    pub(crate) stdin: Stdio,
-    pub(crate) stdout: Stdio,
-    pub(crate) stderr: Stdio,
+    /// The stdio handles are taken when the process is created, since [`Stdio`] can't be cloned
+    #[cfg_attr(feature = "serde", serde(skip, default = "inherit"))]
+    pub(crate) stdin: Option<Stdio>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "inherit"))]
+    pub(crate) stdout: Option<Stdio>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "inherit"))]
+    pub(crate) stderr: Option<Stdio>,
+}
+
+#[cfg(feature = "serde")]
+#[expect(clippy::unnecessary_wraps, reason = "used as a serde default")]
+fn inherit() -> Option<Stdio> {
+    Some(Stdio::inherit())
 }
 
 impl Command {
@@ -50,9 +61,9 @@ impl Command {
         let program = program.as_ref().to_os_string();
         Self {
             program,
-            stdout: Stdio::inherit(),
-            stderr: Stdio::inherit(),
-            stdin: Stdio::inherit(),
+            stdout: Some(Stdio::inherit()),
+            stderr: Some(Stdio::inherit()),
+            stdin: Some(Stdio::inherit()),
             args: Vec::default(),
         }
     }
@@ -151,7 +162,7 @@ impl Command {
     /// Command::new("ls").stdin(Stdio::null());
     /// ```
     pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
-        self.stdin = cfg.into();
+        self.stdin = Some(cfg.into());
         self
     }
 
@@ -172,7 +183,7 @@ impl Command {
     /// Command::new("ls").stdout(Stdio::null());
     /// ```
     pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
-        self.stdout = cfg.into();
+        self.stdout = Some(cfg.into());
         self
     }
 
@@ -193,7 +204,7 @@ impl Command {
     /// Command::new("ls").stderr(Stdio::null());
     /// ```
     pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
-        self.stderr = cfg.into();
+        self.stderr = Some(cfg.into());
         self
     }
 
@@ -212,28 +223,60 @@ impl Command {
     pub fn get_program(&self) -> &OsStr {
         OsStr::from_bytes(self.program.as_bytes())
     }
-}
 
-impl From<Command> for std::process::Command {
-    fn from(command: Command) -> Self {
-        let mut std_command = std::process::Command::new(command.program);
-        std_command.args(command.args);
+    /// Returns the arguments that will be passed to the program.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kincir_bwrap::Command;
+    ///
+    /// let mut cmd = Command::new("ls");
+    /// cmd.arg("-l").arg("-a");
+    /// assert_eq!(cmd.get_args(), ["-l", "-a"]);
+    /// ```
+    #[must_use]
+    pub fn get_args(&self) -> &[OsString] {
+        &self.args
+    }
+}
 
-        let stdin: Option<std::process::Stdio> = command.stdin.into();
-        if let Some(stdin) = stdin {
+impl Command {
+    /// Move the configured stdio handles into `std_command`.
+    ///
+    /// The handles can only be given once, the following calls leave the stdio of `std_command`
+    /// untouched (which means inheriting them by default)
+    pub(crate) fn take_stdio(&mut self, std_command: &mut std::process::Command) {
+        if let Some(stdin) = self.stdin.take() {
             std_command.stdin(stdin);
         }
-
-        let stdout: Option<std::process::Stdio> = command.stdout.into();
-        if let Some(stdout) = stdout {
+        if let Some(stdout) = self.stdout.take() {
             std_command.stdout(stdout);
         }
-
-        let stderr: Option<std::process::Stdio> = command.stderr.into();
-        if let Some(stderr) = stderr {
+        if let Some(stderr) = self.stderr.take() {
             std_command.stderr(stderr);
         }
+    }
+}
 
+/// The stdio handles can't be cloned, the clone inherits the stdio of the current process instead
+impl Clone for Command {
+    fn clone(&self) -> Self {
+        Self {
+            program: self.program.clone(),
+            args: self.args.clone(),
+            stdin: Some(Stdio::inherit()),
+            stdout: Some(Stdio::inherit()),
+            stderr: Some(Stdio::inherit()),
+        }
+    }
+}
+
+impl From<Command> for std::process::Command {
+    fn from(mut command: Command) -> Self {
+        let mut std_command = std::process::Command::new(&command.program);
+        std_command.args(&command.args);
+        command.take_stdio(&mut std_command);
         std_command
     }
 }
@@ -243,9 +286,9 @@ impl<T: AsRef<OsStr>> From<T> for Command {
         Self {
             args: Vec::new(),
             program: value.as_ref().to_os_string(),
-            stdin: Stdio::inherit(),
-            stdout: Stdio::inherit(),
-            stderr: Stdio::inherit(),
+            stdin: Some(Stdio::inherit()),
+            stdout: Some(Stdio::inherit()),
+            stderr: Some(Stdio::inherit()),
         }
     }
 }
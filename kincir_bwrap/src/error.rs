@@ -0,0 +1,71 @@
+use std::ffi::OsString;
+
+/// An error that makes the bwrap arguments impossible to build
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BwrapBuildError {
+    /// A read-only overlay was given a `RWSRC`/`WORKDIR` pair, which bwrap can't use
+    ReadOnlyOverlayWithRw { destination: OsString },
+    /// Overlay layers were added with [`crate::BwrapCommand::overlay_src`] without any overlay
+    /// using them afterwards
+    DanglingOverlaySource { sources: Vec<OsString> },
+    /// Arguments were added to the program while its command line is read from a fd (see
+    /// [`crate::BwrapCommand::args_fd`])
+    ArgsWithArgsFd,
+    /// An existing user namespace is joined (see [`crate::BwrapCommand::userns`]) while a new one
+    /// is also requested with [`crate::NsFlags::USER`], [`crate::NsFlags::USER_TRY`] or
+    /// [`crate::NsFlags::ALL`]
+    UserNsWithUnshareUser,
+    /// A seccomp filter was set with [`crate::BwrapCommand::seccomp`] while others are added with
+    /// [`crate::BwrapCommand::add_seccomp_fd`], which bwrap refuses
+    SeccompWithAddSeccompFd,
+    /// Two different options mount something at the same destination (see
+    /// [`crate::BwrapCommand::validate`]), `first` and `second` are their
+    /// [`crate::FsOptions::describe`]
+    DuplicateDestination {
+        destination: OsString,
+        first: String,
+        second: String,
+    },
+}
+
+impl std::fmt::Display for BwrapBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadOnlyOverlayWithRw { destination } => write!(
+                f,
+                "read-only overlay at {} can't have a rwsrc/workdir",
+                destination.to_string_lossy()
+            ),
+            Self::DanglingOverlaySource { sources } => {
+                write!(f, "overlay sources without any overlay:")?;
+                for source in sources {
+                    write!(f, " {}", source.to_string_lossy())?;
+                }
+                Ok(())
+            }
+            Self::ArgsWithArgsFd => write!(
+                f,
+                "the program can't be given arguments when they are read from a fd"
+            ),
+            Self::UserNsWithUnshareUser => write!(
+                f,
+                "can't join an existing user namespace while unsharing the user namespace"
+            ),
+            Self::SeccompWithAddSeccompFd => {
+                write!(f, "--seccomp can't be combined with --add-seccomp-fd")
+            }
+            Self::DuplicateDestination {
+                destination,
+                first,
+                second,
+            } => write!(
+                f,
+                "both `{first}` and `{second}` mount at {}",
+                destination.to_string_lossy()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BwrapBuildError {}
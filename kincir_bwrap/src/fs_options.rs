@@ -1,4 +1,9 @@
-use std::{ffi::OsString, os::fd::AsRawFd};
+use std::{
+    ffi::{OsStr, OsString},
+    os::fd::AsRawFd,
+};
+
+use crate::BwrapBuildError;
 
 macro_rules! vec_size {
     ($default_size:literal) => {
@@ -21,7 +26,7 @@ macro_rules! vec_size {
 macro_rules! vec_append {
     (@perm: &mut $vec:ident, $permission:ident) => {
         if let Some(p) = $permission.as_ref() {
-            $vec.push(OsString::from("--perm"));
+            $vec.push(OsString::from("--perms"));
             $vec.push(OsString::from(format!("{p:o}")));
         }
     };
@@ -55,13 +60,19 @@ macro_rules! bwrap_flag {
         OsString::from(match ($bool_ro, $bool_try) {
             (true, true) => concat!("--ro-", $flag, "-try"),
             (false, true) => concat!("--", $flag, "-try"),
-            (true, false) => concat!("--ro", $flag, ""),
+            (true, false) => concat!("--ro-", $flag, ""),
             (false, false) => concat!("--", $flag, ""),
         })
     };
 }
 
-#[derive(Debug)]
+/// # Serde
+///
+/// With the `serde` feature the fds of [`FsOptions::File`], [`FsOptions::Data`] and
+/// [`FsOptions::BindFd`] are serialized as their raw number, and these options can't be
+/// deserialized
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub enum FsOptions<'fd> {
     /// the equivalent of `--bind` (with the `read_only` or try modifier if set)
@@ -167,6 +178,13 @@ pub enum FsOptions<'fd> {
     File {
         /// The filedescriptor that will be used in the `--file` flag. Please check the manpage of
         /// `bwrap(1)` to see more information about it
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "crate::serde_helpers::fd",
+                deserialize_with = "crate::serde_helpers::deserialize_fd"
+            )
+        )]
         source: std::os::fd::BorrowedFd<'fd>,
         /// Where does the bind lives while inside of the sandbox
         destination: OsString,
@@ -176,6 +194,13 @@ pub enum FsOptions<'fd> {
     Data {
         /// The filedescriptor that will be used in the `--bind-data` flag. Please check the manpage of
         /// `bwrap(1)` to see more information about it
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "crate::serde_helpers::fd",
+                deserialize_with = "crate::serde_helpers::deserialize_fd"
+            )
+        )]
         source: std::os::fd::BorrowedFd<'fd>,
         /// Where does the bind lives while inside of the sandbox
         destination: OsString,
@@ -186,6 +211,48 @@ pub enum FsOptions<'fd> {
         /// permission would allow it
         read_only: bool,
     },
+    /// the equivalent of `--bind-fd` (or `--ro-bind-fd` if `read_only` is set)
+    /// This binds the directory referenced by an already opened fd, meaning that the bind is
+    /// exactly that directory even if its path on the host changed since it was opened
+    BindFd {
+        /// The fd of the directory that will be bound
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "crate::serde_helpers::fd",
+                deserialize_with = "crate::serde_helpers::deserialize_fd"
+            )
+        )]
+        source_fd: std::os::fd::BorrowedFd<'fd>,
+        /// Where does the bind lives while inside of the sandbox
+        destination: OsString,
+        /// This makes sure that the sandbox can't write the the files under the bind, even if the
+        /// permission would allow it
+        read_only: bool,
+    },
+    /// Mount an overlayfs at `destination`, the equivalent of `--overlay-src` (for each source)
+    /// followed by:
+    /// - `--overlay` if `rw` is set
+    /// - `--ro-overlay` if `read_only` is set
+    /// - `--tmp-overlay` otherwise, meaning that the writes go to an invisible tmpfs and are
+    ///   lost when the sandbox exits
+    ///
+    /// # Note
+    /// an overlay without any source won't produce any flag
+    Overlay {
+        /// Where does the overlay lives while inside of the sandbox
+        destination: OsString,
+        /// The lower layers of the overlay (outside of the sandbox), the lowest layer first
+        sources: Vec<OsString>,
+        /// The `RWSRC` and `WORKDIR` of the overlay (outside of the sandbox).
+        /// The writes in the overlay will end up in `RWSRC`, and `WORKDIR` must be an empty
+        /// directory on the same filesystem
+        ///
+        /// This must not be set for read-only overlays
+        rw: Option<(OsString, OsString)>,
+        /// This would mean `--ro-overlay` if set
+        read_only: bool,
+    },
     /// Change the permission of an existing file inside the sandbox
     Chmod {
         /// Which file/directory/path to change the permission
@@ -194,9 +261,166 @@ pub enum FsOptions<'fd> {
         /// looking from inside the sandbox
         permission: u64,
     },
+    /// Take a shared lock on an existing file for the lifetime of the sandbox, the equivalent of
+    /// `--lock-file`.
+    ///
+    /// The lock is taken when the option is reached, meaning after the options added before it
+    LockFile {
+        /// Which file to lock, as seen from inside the sandbox
+        destination: OsString,
+    },
+    /// Remount an existing path read-only inside the sandbox, the equivalent of `--remount-ro`.
+    ///
+    /// This only affects what is already mounted at that point, so it must come after the bind
+    /// it modifies
+    RemountRo {
+        /// Which path to remount, as seen from inside the sandbox
+        destination: OsString,
+    },
+    /// Set the permission of the file or directory created by the next option, the equivalent of
+    /// `--perms`.
+    ///
+    /// bwrap applies it to the option that immediately follows, so this must be inserted right
+    /// before the option it modifies (such as [`FsOptions::Dir`] or [`FsOptions::TempFs`]).
+    /// Prefer the `permission` field of the options when they have one
+    Perms {
+        /// The permission, as an octal mode such as `0o755`
+        perm: u64,
+    },
+    /// Set the size of the next tmpfs, the equivalent of `--size`.
+    ///
+    /// bwrap applies it to the option that immediately follows, so this must be inserted right
+    /// before the [`FsOptions::TempFs`] it modifies. Prefer the `size` field of
+    /// [`FsOptions::TempFs`]
+    SetSize {
+        /// The maximum size in bytes
+        size: usize,
+    },
 }
 
-impl<'fd> FsOptions<'fd> {
+impl FsOptions<'_> {
+    /// Check that the option can be turned into valid bwrap arguments
+    ///
+    /// # Errors
+    ///
+    /// see [`BwrapBuildError`] for the possible errors
+    pub fn validate(&self) -> Result<(), BwrapBuildError> {
+        match self {
+            Self::Overlay {
+                destination,
+                rw,
+                read_only,
+                ..
+            } if rw.is_some() && *read_only => Err(BwrapBuildError::ReadOnlyOverlayWithRw {
+                destination: destination.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Where the option mounts something inside the sandbox, or `None` for the options that only
+    /// modify what is already there (such as [`FsOptions::Dir`] or [`FsOptions::Chmod`])
+    pub(crate) fn mount_destination(&self) -> Option<&OsStr> {
+        match self {
+            Self::Bind { destination, .. }
+            | Self::DevBind { destination, .. }
+            | Self::ProcBind { destination, .. }
+            | Self::Dev { destination, .. }
+            | Self::Proc { destination, .. }
+            | Self::MQueue { destination, .. }
+            | Self::TempFs { destination, .. }
+            | Self::Symlink { destination, .. }
+            | Self::File { destination, .. }
+            | Self::Data { destination, .. }
+            | Self::BindFd { destination, .. } => Some(destination),
+            Self::Overlay {
+                destination,
+                sources,
+                ..
+            } => (!sources.is_empty()).then_some(destination.as_os_str()),
+            Self::Dir { .. }
+            | Self::Chmod { .. }
+            | Self::LockFile { .. }
+            | Self::RemountRo { .. }
+            | Self::Perms { .. }
+            | Self::SetSize { .. } => None,
+        }
+    }
+
+    /// A human readable description of the option, such as `ro-bind /usr -> /usr` or
+    /// `tmpfs -> /tmp (size 64M)`.
+    ///
+    /// This is meant for logs, use [`FsOptions::to_option`] for the actual bwrap arguments
+    #[must_use]
+    pub fn describe(&self) -> String {
+        let lossy = |s: &OsString| s.to_string_lossy().into_owned();
+        match self {
+            Self::Chmod {
+                destination,
+                permission,
+            } => format!("chmod {} (mode {permission:o})", lossy(destination)),
+            Self::Perms { perm } => format!("perms {perm:o} (for the next option)"),
+            Self::SetSize { size } => format!("size {} (for the next option)", human_size(*size)),
+            Self::Overlay {
+                destination,
+                sources,
+                rw,
+                read_only,
+            } => {
+                let kind = match (read_only, rw) {
+                    (true, _) => "ro-overlay",
+                    (false, Some(_)) => "overlay",
+                    (false, None) => "tmp-overlay",
+                };
+                let sources = sources.iter().map(lossy).collect::<Vec<_>>().join(" + ");
+                let out = format!("{kind} {sources} -> {}", lossy(destination));
+                match rw {
+                    Some((rwsrc, _)) => format!("{out} (writes in {})", lossy(rwsrc)),
+                    None => out,
+                }
+            }
+            _ => {
+                // every other option is `[--perms PERMS] [--size SIZE] --FLAG [SOURCE] DESTINATION`
+                let args = self.to_option().into_iter().collect::<Vec<_>>();
+                let mut details = Vec::new();
+                let mut rest = args.as_slice();
+                while let [flag, value, tail @ ..] = rest {
+                    match flag.to_str() {
+                        Some("--perms") => details.push(format!("mode {}", lossy(value))),
+                        Some("--size") => details.push(format!(
+                            "size {}",
+                            value
+                                .to_str()
+                                .and_then(|v| v.parse().ok())
+                                .map_or_else(|| lossy(value), human_size)
+                        )),
+                        _ => break,
+                    }
+                    rest = tail;
+                }
+                let out = match rest {
+                    [flag, source, destination] => format!(
+                        "{} {} -> {}",
+                        flag.to_string_lossy().trim_start_matches('-'),
+                        lossy(source),
+                        lossy(destination)
+                    ),
+                    [flag, destination] => format!(
+                        "{} -> {}",
+                        flag.to_string_lossy().trim_start_matches('-'),
+                        lossy(destination)
+                    ),
+                    _ => rest.iter().map(lossy).collect::<Vec<_>>().join(" "),
+                };
+                if details.is_empty() {
+                    out
+                } else {
+                    format!("{out} ({})", details.join(", "))
+                }
+            }
+        }
+    }
+
     #[expect(clippy::too_many_lines)]
     #[must_use]
     pub fn to_option(&self) -> impl IntoIterator<Item = OsString> {
@@ -209,6 +433,40 @@ impl<'fd> FsOptions<'fd> {
                 OsString::from(format!("{permission:o}")),
                 destination.clone(),
             ],
+            Self::Perms { perm } => vec![bwrap_flag!(@none: "perms"), format!("{perm:o}").into()],
+            Self::SetSize { size } => vec![bwrap_flag!(@none: "size"), size.to_string().into()],
+            Self::LockFile { destination } => {
+                vec![bwrap_flag!(@none: "lock-file"), destination.clone()]
+            }
+            Self::RemountRo { destination } => {
+                vec![bwrap_flag!(@none: "remount-ro"), destination.clone()]
+            }
+            Self::Overlay {
+                destination,
+                sources,
+                rw,
+                read_only,
+            } => {
+                if sources.is_empty() {
+                    return Vec::new();
+                }
+                let mut v = Vec::with_capacity(sources.len() * 2 + 4);
+                for source in sources {
+                    v.push(bwrap_flag!(@none: "overlay-src"));
+                    v.push(source.clone());
+                }
+                if *read_only {
+                    v.push(bwrap_flag!(@none: "ro-overlay"));
+                } else if let Some((rwsrc, workdir)) = rw {
+                    v.push(bwrap_flag!(@none: "overlay"));
+                    v.push(rwsrc.clone());
+                    v.push(workdir.clone());
+                } else {
+                    v.push(bwrap_flag!(@none: "tmp-overlay"));
+                }
+                v.push(destination.clone());
+                v
+            }
             Self::Data {
                 source,
                 destination,
@@ -222,6 +480,15 @@ impl<'fd> FsOptions<'fd> {
                 v.push(destination.clone());
                 v
             }
+            Self::BindFd {
+                source_fd,
+                destination,
+                read_only,
+            } => vec![
+                bwrap_flag!(@ro: "bind-fd", *read_only),
+                source_fd.as_raw_fd().to_string().into(),
+                destination.clone(),
+            ],
             Self::File {
                 source,
                 destination,
@@ -339,3 +606,13 @@ impl<'fd> FsOptions<'fd> {
         }
     }
 }
+
+/// Format a size in bytes with the biggest unit dividing it (`64M` for 64 MiB)
+fn human_size(size: usize) -> String {
+    for (unit, shift) in [("G", 30), ("M", 20), ("K", 10)] {
+        if size != 0 && size.is_multiple_of(1 << shift) {
+            return format!("{}{unit}", size >> shift);
+        }
+    }
+    size.to_string()
+}
@@ -0,0 +1,165 @@
+use std::io::{self, BufRead, Read};
+
+/// The information bwrap writes on the `--info-fd` once the sandbox is set up (see
+/// [`crate::BwrapCommand::info_fd`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SandboxInfo {
+    /// The pid of the sandboxed process, as seen from outside of the sandbox
+    pub child_pid: u32,
+}
+
+impl SandboxInfo {
+    /// Parse the JSON object written by bwrap, such as `{ "child-pid": 1234 }`.
+    ///
+    /// The other keys are ignored, this returns `None` if `child-pid` is missing
+    #[must_use]
+    pub fn parse(json: &str) -> Option<Self> {
+        Some(Self {
+            child_pid: json_integer(json, "child-pid")?.try_into().ok()?,
+        })
+    }
+
+    /// Read the JSON object written by bwrap from `reader` (the read end of the info fd).
+    ///
+    /// This stops at the end of the object, so it doesn't wait for the fd to be closed.
+    ///
+    /// # Errors
+    ///
+    /// This fails if reading fails, or if the object doesn't contain a valid `child-pid`
+    /// ([`io::ErrorKind::InvalidData`])
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut json = Vec::new();
+        let mut chunk = [0; 256];
+        while !json.contains(&b'}') {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            json.extend_from_slice(&chunk[..read]);
+        }
+        Self::parse(&String::from_utf8_lossy(&json)).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bwrap info doesn't contain a valid child-pid",
+            )
+        })
+    }
+}
+
+/// An event written by bwrap on the `--json-status-fd` (see
+/// [`crate::BwrapCommand::json_status_fd`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BwrapStatus {
+    /// The sandbox is set up and the program is running
+    Started {
+        /// The pid of the sandboxed process, as seen from outside of the sandbox
+        child_pid: u32,
+    },
+    /// The sandboxed program exited
+    Exited {
+        /// The exit code of the program, or `128 + signal` if it was killed by a signal
+        exit_code: i32,
+    },
+}
+
+impl BwrapStatus {
+    /// Parse one line written by bwrap, returning `None` for unknown events
+    #[must_use]
+    pub fn parse(line: &str) -> Option<Self> {
+        if let Some(exit_code) = json_integer(line, "exit-code") {
+            return Some(Self::Exited {
+                exit_code: exit_code.try_into().ok()?,
+            });
+        }
+        Some(Self::Started {
+            child_pid: json_integer(line, "child-pid")?.try_into().ok()?,
+        })
+    }
+
+    /// Read the events written by bwrap from `reader` (the read end of the status fd), until it
+    /// is closed.
+    ///
+    /// The events are newline-delimited, a line split across multiple reads is only parsed once
+    /// complete. Unknown events are skipped.
+    pub fn stream(reader: impl Read) -> impl Iterator<Item = io::Result<Self>> {
+        io::BufReader::new(reader)
+            .split(b'\n')
+            .filter_map(|line| match line {
+                Ok(line) => Self::parse(&String::from_utf8_lossy(&line)).map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+    }
+}
+
+/// Find the integer value of `key` in a flat JSON object, as written by bwrap
+pub(crate) fn json_integer(json: &str, key: &str) -> Option<i64> {
+    let quoted = format!("\"{key}\"");
+    let rest = &json[json.find(&quoted)? + quoted.len()..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '-'))
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = "{\n    \"child-pid\": 4242,\n    \"cgroup-namespace\": 4026531835,\n    \"ipc-namespace\": 4026532281\n}\n";
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            SandboxInfo::parse(INFO),
+            Some(SandboxInfo { child_pid: 4242 })
+        );
+        assert_eq!(
+            SandboxInfo::parse("{\"child-pid\":7}"),
+            Some(SandboxInfo { child_pid: 7 })
+        );
+        assert_eq!(SandboxInfo::parse("{\"child-pid\": -1}"), None);
+        assert_eq!(SandboxInfo::parse("{}"), None);
+    }
+
+    /// A reader giving back at most 3 bytes at a time
+    struct Slow<'a>(&'a [u8]);
+
+    impl Read for Slow<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn status() {
+        let stream = "{ \"child-pid\": 4242, \"cgroup-namespace\": 4026531835 }\n{ \"unknown\": 1 }\n{ \"exit-code\": 137 }\n";
+        let events = BwrapStatus::stream(Slow(stream.as_bytes()))
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            events,
+            [
+                BwrapStatus::Started { child_pid: 4242 },
+                BwrapStatus::Exited { exit_code: 137 }
+            ]
+        );
+    }
+
+    #[test]
+    fn read() {
+        assert_eq!(
+            SandboxInfo::read(INFO.as_bytes()).unwrap(),
+            SandboxInfo { child_pid: 4242 }
+        );
+        let error = SandboxInfo::read(&b"{\n}\n"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -4,26 +4,185 @@
 //! This is a crate that aims to wrap bubblewrap cli tool to be used in rust without forcing the
 //! use of raw CLI api
 
+mod capabilities;
 mod command;
+mod error;
 mod fs_options;
+mod info;
 mod namespace;
-use std::collections::{HashMap, HashSet};
+mod oci;
+#[cfg(test)]
+mod proptests;
+#[cfg(feature = "serde")]
+mod serde_helpers;
+mod shell;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::{OsStr, OsString};
-use std::os::fd::AsFd;
+use std::os::fd::{AsFd, AsRawFd};
 use std::path::Path;
 
 pub use command::Command;
+pub use error::BwrapBuildError;
 pub use fs_options::FsOptions;
+pub use info::{BwrapStatus, SandboxInfo};
 pub use namespace::NsFlags;
 pub use namespace::NsOptions;
+pub use oci::OciMount;
+
+/// An argument given to bwrap, as yielded by [`BwrapCommand::args_iter`].
+///
+/// This is a [`Cow`] so that the values stored in the command (such as the program and its
+/// arguments) are borrowed, while the ones computed while building (such as the fds) are owned
+pub type CowStr<'a> = Cow<'a, OsStr>;
 
-#[derive(Debug)]
+/// The locations where `libfaketime` is usually installed
+const FAKETIME_LIBRARIES: &[&str] = &[
+    "/usr/lib/x86_64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib/aarch64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib64/faketime/libfaketime.so.1",
+    "/usr/lib/faketime/libfaketime.so.1",
+    "/usr/local/lib/faketime/libfaketime.so.1",
+];
+
+/// A builder for the arguments of bwrap, and the program it runs in the sandbox.
+///
+/// # Cloning
+///
+/// The stdio handles given to the program (see [`Command::stdout`]) can't be cloned, a clone
+/// inherits the stdio of the current process instead
+///
+/// # Serde
+///
+/// With the `serde` feature the command can be serialized, for example to keep the exact
+/// description of a sandbox. The stdio handles are skipped, and the fds given to the sandbox (such
+/// as with [`BwrapCommand::seccomp`]) are serialized as their raw number, and a command holding
+/// one fails to deserialize, since they are only valid in the process owning them
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BwrapCommand<'fd> {
     bwrap: Option<OsString>,
     clear_env: bool,
-    env: HashMap<OsString, OsString>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            serialize_with = "serde_helpers::env",
+            deserialize_with = "serde_helpers::deserialize_env"
+        )
+    )]
+    env: BTreeMap<OsString, OsString>,
     fs_options: Vec<fs_options::FsOptions<'fd>>,
-    unset_env: HashSet<OsString>,
+    unset_env: BTreeSet<OsString>,
+    /// The overlay layers added with [`BwrapCommand::overlay_src`] that will be used by the next
+    /// overlay
+    pending_overlay_src: Vec<OsString>,
+    /// The directories (inside the sandbox) appended to the `PATH`
+    path_dirs: Vec<OsString>,
+    /// The name given to the program as its `argv[0]` (`--argv0`), instead of its path
+    argv0: Option<OsString>,
+    /// The security context the program is executed with (`--exec-label`)
+    exec_label: Option<OsString>,
+    /// The security label of the files created in the sandbox (`--file-label`)
+    file_label: Option<OsString>,
+    /// The capabilities added to (`--cap-add`) or dropped from (`--cap-drop`) the sandbox, as
+    /// pairs of flag and capability.
+    ///
+    /// They are kept in a single list since bwrap applies them in order
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "serde_helpers::capabilities")
+    )]
+    capabilities: Vec<(&'static str, OsString)>,
+    /// The seccomp program loaded in the sandbox (`--seccomp`)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    seccomp: Option<std::os::fd::BorrowedFd<'fd>>,
+    /// The fd the command line is read from (`--args`), instead of the program and its arguments
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    args_fd: Option<std::os::fd::BorrowedFd<'fd>>,
+    /// The seccomp programs stacked on top of each other (`--add-seccomp-fd`), in order
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::fds",
+            deserialize_with = "serde_helpers::deserialize_fds"
+        )
+    )]
+    add_seccomp: Vec<std::os::fd::BorrowedFd<'fd>>,
+    /// The fd kept open by bwrap as long as the sandbox is running (`--sync-fd`)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    sync_fd: Option<std::os::fd::BorrowedFd<'fd>>,
+    /// The fd on which bwrap writes information about the sandbox (`--info-fd`)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    info_fd: Option<std::os::fd::BorrowedFd<'fd>>,
+    /// The fd on which bwrap streams the status of the sandbox (`--json-status-fd`)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    json_status_fd: Option<std::os::fd::BorrowedFd<'fd>>,
+    /// The existing user namespace joined by the sandbox (`--userns`)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    userns: Option<std::os::fd::BorrowedFd<'fd>>,
+    /// The user namespace switched into once the sandbox is set up (`--userns2`)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    userns2: Option<std::os::fd::BorrowedFd<'fd>>,
+    /// The existing pid namespace joined by the sandbox (`--pidns`)
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            serialize_with = "serde_helpers::option_fd",
+            deserialize_with = "serde_helpers::deserialize_option_fd"
+        )
+    )]
+    pidns: Option<std::os::fd::BorrowedFd<'fd>>,
     ns_options: NsOptions,
     command: command::Command,
 }
@@ -46,14 +205,86 @@ impl<'fd> BwrapCommand<'fd> {
         Self {
             bwrap: None,
             clear_env: false,
-            env: HashMap::new(),
-            unset_env: HashSet::new(),
+            env: BTreeMap::new(),
+            unset_env: BTreeSet::new(),
+            pending_overlay_src: Vec::new(),
+            path_dirs: Vec::new(),
+            argv0: None,
+            exec_label: None,
+            file_label: None,
+            capabilities: Vec::new(),
+            seccomp: None,
+            add_seccomp: Vec::new(),
+            args_fd: None,
+            sync_fd: None,
+            info_fd: None,
+            json_status_fd: None,
+            userns: None,
+            userns2: None,
+            pidns: None,
             fs_options: Vec::new(),
             ns_options: NsOptions::new(),
             command: cmd.into(),
         }
     }
 
+    #[must_use]
+    /// Create a new bwrap command with the filesystem described by a list of OCI mounts (the
+    /// `mounts` key of an OCI `config.json`).
+    ///
+    /// Only the `bind` and `tmpfs` mounts are supported, the other ones are ignored with a
+    /// warning.
+    pub fn from_oci_mounts(cmd: impl Into<command::Command>, mounts: &[OciMount]) -> Self {
+        let mut this = Self::new(cmd);
+        for mount in mounts {
+            if let Some(option) = mount.to_fs_option() {
+                this.add_fs_options(option);
+            } else {
+                log::warn!(
+                    "unsupported OCI mount at {} (type: {:?}), ignoring it",
+                    mount.destination.display(),
+                    mount.kind
+                );
+            }
+        }
+        this
+    }
+
+    #[must_use]
+    /// Create a new bwrap command running an applet of a multi-call binary (such as `busybox`).
+    ///
+    /// The binary is bound read-only at the same path inside the sandbox, and is executed with
+    /// `applet` as its `argv[0]`, which is how these binaries choose the applet to run.
+    ///
+    /// ```
+    ///     # use kincir_bwrap::*;
+    ///
+    ///     let args = BwrapCommand::multicall("/usr/bin/busybox", "ls", ["-l"])
+    ///         .build_args()
+    ///         .unwrap();
+    ///     assert_eq!(args[3..], ["--argv0", "ls", "--", "/usr/bin/busybox", "-l"]);
+    /// ```
+    pub fn multicall(
+        binary: impl AsRef<Path>,
+        applet: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    ) -> Self {
+        let binary = binary.as_ref();
+        let mut this = Self::new(binary.as_os_str());
+        this.bind_read_only(binary, binary);
+        this.argv0(applet);
+        for arg in args {
+            this.arg(arg);
+        }
+        this
+    }
+
+    /// Start the sandbox with an empty environment (`--clearenv`), only the variables added with
+    /// [`BwrapCommand::add_env`] are set.
+    ///
+    /// Enabling it forgets the variables set or unset so far. The ones unset afterwards with
+    /// [`BwrapCommand::add_unset_env`] are kept but not given to bwrap, since there is nothing to
+    /// unset, they are only given if this is disabled again
     pub fn clear_env(&mut self, clear_env: bool) -> &mut Self {
         if clear_env {
             self.clear_env = true;
@@ -71,6 +302,20 @@ impl<'fd> BwrapCommand<'fd> {
         self
     }
 
+    /// Set multiple variables at once, the same as calling [`BwrapCommand::add_env`] for each
+    /// of them
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self.add_env(key, value);
+        }
+        self
+    }
+
     pub fn add_unset_env(&mut self, key: impl AsRef<OsStr>) -> &mut Self {
         self.unset_env.insert(key.as_ref().to_os_string());
         self
@@ -86,26 +331,150 @@ impl<'fd> BwrapCommand<'fd> {
         self
     }
 
+    /// Append a directory (as seen from inside the sandbox) to the `PATH` of the sandbox.
+    ///
+    /// The directories are appended to the `PATH` set with [`BwrapCommand::add_env`], or to the
+    /// one inherited from the current process if there is none (and the environment isn't
+    /// cleared or the `PATH` unset)
+    pub fn add_to_path(&mut self, guest_dir: impl AsRef<Path>) -> &mut Self {
+        let dir = guest_dir.as_ref().as_os_str().to_os_string();
+        if !self.path_dirs.contains(&dir) {
+            self.path_dirs.push(dir);
+        }
+        self
+    }
+
+    /// Bind a directory read-only and add it to the `PATH` of the sandbox, see
+    /// [`BwrapCommand::add_to_path`]
+    pub fn bind_to_path(&mut self, host: impl AsRef<Path>, guest: impl AsRef<Path>) -> &mut Self {
+        self.bind_read_only(host, &guest).add_to_path(guest)
+    }
+
+    /// The `PATH` of the sandbox once the directories of [`BwrapCommand::add_to_path`] are added,
+    /// or `None` if there are no such directories
+    fn path_env(&self) -> Option<OsString> {
+        if self.path_dirs.is_empty() {
+            return None;
+        }
+        let base = match self.env.get(OsStr::new("PATH")) {
+            Some(path) => Some(path.clone()),
+            None if self.clear_env || self.unset_env.contains(OsStr::new("PATH")) => None,
+            None => std::env::var_os("PATH"),
+        };
+        let mut path = base.unwrap_or_default();
+        for dir in &self.path_dirs {
+            if !path.is_empty() {
+                path.push(":");
+            }
+            path.push(dir);
+        }
+        Some(path)
+    }
+
     pub fn add_fs_options(&mut self, option: fs_options::FsOptions<'fd>) -> &mut Self {
         self.fs_options.push(option);
         self
     }
 
+    /// Remove every filesystem option, including the overlay layers added with
+    /// [`BwrapCommand::overlay_src`] that weren't used yet
+    pub fn clear_fs_options(&mut self) -> &mut Self {
+        self.fs_options.clear();
+        self.pending_overlay_src.clear();
+        self
+    }
+
+    /// Replace every filesystem option with `options` (see [`BwrapCommand::clear_fs_options`])
+    pub fn set_fs_options(&mut self, options: Vec<fs_options::FsOptions<'fd>>) -> &mut Self {
+        self.clear_fs_options();
+        self.fs_options = options;
+        self
+    }
+
+    /// The filesystem options, in the order they are applied
+    #[must_use]
+    pub fn fs_options(&self) -> &[fs_options::FsOptions<'fd>] {
+        &self.fs_options
+    }
+
     pub fn set_cwd(&mut self, cwd: impl AsRef<std::path::Path>) -> &mut Self {
         self.ns_options.set_cwd(cwd);
         self
     }
 
+    /// Give `name` to the program as its `argv[0]` (`--argv0`), instead of its path.
+    ///
+    /// The same binary is executed, only the name it sees changes (which matters for login
+    /// shells or multi-call binaries, see [`BwrapCommand::multicall`]).
+    pub fn argv0(&mut self, name: impl AsRef<OsStr>) -> &mut Self {
+        self.argv0 = Some(name.as_ref().to_os_string());
+        self
+    }
+
+    /// Execute the program with the security context `label` (`--exec-label`)
+    pub fn exec_label(&mut self, label: impl AsRef<OsStr>) -> &mut Self {
+        self.exec_label = Some(label.as_ref().to_os_string());
+        self
+    }
+
+    /// Give the security label `label` to the files created in the sandbox (`--file-label`)
+    pub fn file_label(&mut self, label: impl AsRef<OsStr>) -> &mut Self {
+        self.file_label = Some(label.as_ref().to_os_string());
+        self
+    }
+
     pub fn unset_cwd(&mut self) -> &mut Self {
         self.ns_options.unset_cwd();
         self
     }
 
+    /// Set the hostname of the sandbox (`--hostname`), this enables [`NsFlags::UTS`]
+    pub fn hostname(&mut self, hostname: impl AsRef<OsStr>) -> &mut Self {
+        self.ns_options.set_hostname(hostname);
+        self
+    }
+
+    /// Set the uid of the program inside the sandbox (`--uid`), this enables [`NsFlags::USER`]
+    /// unless an existing user namespace is joined (see [`BwrapCommand::userns`])
+    pub fn uid(&mut self, uid: impl Into<std::ffi::c_int>) -> &mut Self {
+        self.ns_options.set_uid(uid);
+        self
+    }
+
+    /// Set the gid of the program inside the sandbox (`--gid`), this enables [`NsFlags::USER`]
+    /// unless an existing user namespace is joined (see [`BwrapCommand::userns`])
+    pub fn gid(&mut self, gid: impl Into<std::ffi::c_int>) -> &mut Self {
+        self.ns_options.set_gid(gid);
+        self
+    }
+
+    /// Run the sandbox in a new terminal session (`--new-session`), so that the program can't
+    /// inject input into the terminal of the caller (CVE-2017-5226).
+    ///
+    /// The program is detached from the controlling terminal, meaning that interactive programs
+    /// (shells, editors) won't get job control or `SIGINT`s from the terminal, only enable this
+    /// for non-interactive runs
     pub fn new_session(&mut self, enable: bool) -> &mut Self {
         self.ns_options.flags.set(NsFlags::NEW_SESSION, enable);
         self
     }
 
+    /// Kill the sandbox when the process that launched bwrap dies (`--die-with-parent`), instead
+    /// of leaving it running unattended
+    pub fn die_with_parent(&mut self, enable: bool) -> &mut Self {
+        self.ns_options.flags.set(NsFlags::DIE_WITH_PARENT, enable);
+        self
+    }
+
+    /// Run the program as the pid 1 of the sandbox (`--as-pid-1`) instead of under a reaper
+    /// process set up by bwrap, meaning that the program has to reap the zombie processes itself.
+    ///
+    /// This needs a new pid namespace, so [`NsFlags::PID`] is enabled along with it
+    pub fn as_pid_1(&mut self, enable: bool) -> &mut Self {
+        self.ns_options.flags.set(NsFlags::AS_PID_1, enable);
+        self
+    }
+
     /// this takes the `flags` and add them to the existing flagss
     pub fn add_namespace_flags(&mut self, flags: NsFlags) -> &mut Self {
         self.ns_options.flags.insert(flags);
@@ -140,6 +509,20 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    /// Bind multiple `(host, guest)` pairs at once, the same as calling [`BwrapCommand::bind`]
+    /// for each of them
+    pub fn binds<I, H, G>(&mut self, binds: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (H, G)>,
+        H: AsRef<Path>,
+        G: AsRef<Path>,
+    {
+        for (host, guest) in binds {
+            self.bind(host, guest);
+        }
+        self
+    }
+
     pub fn try_bind(&mut self, host: impl AsRef<Path>, guest: impl AsRef<Path>) -> &mut Self {
         self.add_fs_options(FsOptions::Bind {
             read_only: false,
@@ -174,6 +557,159 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    /// Same as [`BwrapCommand::bind`], but the bind will have the given permission inside the
+    /// sandbox (`--perms`)
+    pub fn bind_with_perm(
+        &mut self,
+        host: impl AsRef<Path>,
+        guest: impl AsRef<Path>,
+        permission: u64,
+    ) -> &mut Self {
+        self.add_fs_options(FsOptions::Bind {
+            read_only: false,
+            source: host.as_ref().as_os_str().to_os_string(),
+            destination: guest.as_ref().as_os_str().to_os_string(),
+            permission: Some(permission),
+            try_: false,
+        })
+    }
+
+    /// Same as [`BwrapCommand::bind_read_only`], but the bind will have the given permission
+    /// inside the sandbox (`--perms`)
+    pub fn bind_read_only_with_perm(
+        &mut self,
+        host: impl AsRef<Path>,
+        guest: impl AsRef<Path>,
+        permission: u64,
+    ) -> &mut Self {
+        self.add_fs_options(FsOptions::Bind {
+            read_only: true,
+            source: host.as_ref().as_os_str().to_os_string(),
+            destination: guest.as_ref().as_os_str().to_os_string(),
+            permission: Some(permission),
+            try_: false,
+        })
+    }
+
+    /// Bind a git worktree read-only, while hiding its `.git` directory behind an empty tmpfs so
+    /// that the sandbox can't read the git internals (history, remotes, ...).
+    ///
+    /// In linked worktrees and submodules `.git` is a file pointing to the git directory, it is
+    /// then hidden behind `/dev/null` instead (a tmpfs can't be mounted on a file)
+    pub fn bind_worktree(&mut self, host: impl AsRef<Path>, guest: impl AsRef<Path>) -> &mut Self {
+        let git = guest.as_ref().join(".git");
+        let is_file = std::fs::metadata(host.as_ref().join(".git")).is_ok_and(|m| m.is_file());
+        self.bind_read_only(host, guest.as_ref());
+        if is_file {
+            self.bind_read_only("/dev/null", git)
+        } else {
+            self.tmpfs(git)
+        }
+    }
+
+    /// Add a lower layer (outside of the sandbox) for the next overlay.
+    ///
+    /// The layers are stacked in the order of the calls, the first one being the lowest, and are
+    /// consumed by the next call to [`BwrapCommand::overlay`], [`BwrapCommand::ro_overlay`] or
+    /// [`BwrapCommand::tmp_overlay`] (below the layers given to that call).
+    ///
+    /// ```
+    ///     # use kincir_bwrap::*;
+    ///
+    ///     let args = BwrapCommand::new("ls")
+    ///         .overlay_src("/usr")
+    ///         .overlay_src("/opt/usr")
+    ///         .tmp_overlay("/usr", Vec::<&str>::new())
+    ///         .build_args()
+    ///         .unwrap();
+    ///     assert_eq!(args[..5], ["--overlay-src", "/usr", "--overlay-src", "/opt/usr", "--tmp-overlay"]);
+    /// ```
+    ///
+    /// It is an error to add layers without any overlay afterwards, see
+    /// [`BwrapBuildError::DanglingOverlaySource`]
+    pub fn overlay_src(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.pending_overlay_src
+            .push(path.as_ref().as_os_str().to_os_string());
+        self
+    }
+
+    /// Take the pending overlay layers, followed by `sources`
+    fn overlay_sources(
+        &mut self,
+        sources: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> Vec<OsString> {
+        let mut layers = std::mem::take(&mut self.pending_overlay_src);
+        layers.extend(
+            sources
+                .into_iter()
+                .map(|s| s.as_ref().as_os_str().to_os_string()),
+        );
+        layers
+    }
+
+    /// Mount an overlay at `destination` made of the `sources` layers (lowest first), with the
+    /// writes going into `rwsrc`.
+    ///
+    /// `workdir` must be an empty directory on the same filesystem as `rwsrc`.
+    /// The layers added with [`BwrapCommand::overlay_src`] are used below `sources`.
+    /// Nothing is mounted if there are no layers.
+    pub fn overlay(
+        &mut self,
+        destination: impl AsRef<Path>,
+        sources: impl IntoIterator<Item = impl AsRef<Path>>,
+        rwsrc: impl AsRef<Path>,
+        workdir: impl AsRef<Path>,
+    ) -> &mut Self {
+        let sources = self.overlay_sources(sources);
+        self.add_fs_options(FsOptions::Overlay {
+            destination: destination.as_ref().as_os_str().to_os_string(),
+            sources,
+            rw: Some((
+                rwsrc.as_ref().as_os_str().to_os_string(),
+                workdir.as_ref().as_os_str().to_os_string(),
+            )),
+            read_only: false,
+        })
+    }
+
+    /// Mount a read-only overlay at `destination` made of the `sources` layers (lowest first).
+    ///
+    /// The layers added with [`BwrapCommand::overlay_src`] are used below `sources`.
+    /// Nothing is mounted if there are no layers.
+    pub fn ro_overlay(
+        &mut self,
+        destination: impl AsRef<Path>,
+        sources: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> &mut Self {
+        let sources = self.overlay_sources(sources);
+        self.add_fs_options(FsOptions::Overlay {
+            destination: destination.as_ref().as_os_str().to_os_string(),
+            sources,
+            rw: None,
+            read_only: true,
+        })
+    }
+
+    /// Mount an overlay at `destination` made of the `sources` layers (lowest first), where the
+    /// writes go to an invisible tmpfs.
+    ///
+    /// This lets the sandbox modify a directory without persisting any change.
+    /// The layers added with [`BwrapCommand::overlay_src`] are used below `sources`.
+    /// Nothing is mounted if there are no layers.
+    pub fn tmp_overlay(
+        &mut self,
+        destination: impl AsRef<Path>,
+        sources: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> &mut Self {
+        let sources = self.overlay_sources(sources);
+        self.add_fs_options(FsOptions::Overlay {
+            destination: destination.as_ref().as_os_str().to_os_string(),
+            sources,
+            rw: None,
+            read_only: false,
+        })
+    }
+
     pub fn proc_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
         self.add_fs_options(FsOptions::Proc {
             destination: path.as_ref().as_os_str().to_os_string(),
@@ -197,6 +733,22 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    /// Same as [`BwrapCommand::dev_bind`], but the bind will have the given permission inside
+    /// the sandbox (`--perms`)
+    pub fn dev_bind_with_perm(
+        &mut self,
+        host: impl AsRef<Path>,
+        guest: impl AsRef<Path>,
+        permission: u64,
+    ) -> &mut Self {
+        self.add_fs_options(FsOptions::DevBind {
+            source: host.as_ref().as_os_str().to_os_string(),
+            destination: guest.as_ref().as_os_str().to_os_string(),
+            permission: Some(permission),
+            try_: false,
+        })
+    }
+
     pub fn try_dev_bind(&mut self, host: impl AsRef<Path>, guest: impl AsRef<Path>) -> &mut Self {
         self.add_fs_options(FsOptions::DevBind {
             source: host.as_ref().as_os_str().to_os_string(),
@@ -206,6 +758,31 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    pub fn mqueue(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.add_fs_options(FsOptions::MQueue {
+            destination: path.as_ref().as_os_str().to_os_string(),
+            permission: None,
+        })
+    }
+
+    pub fn proc_bind(&mut self, host: impl AsRef<Path>, guest: impl AsRef<Path>) -> &mut Self {
+        self.add_fs_options(FsOptions::ProcBind {
+            source: host.as_ref().as_os_str().to_os_string(),
+            destination: guest.as_ref().as_os_str().to_os_string(),
+            permission: None,
+            try_: false,
+        })
+    }
+
+    pub fn try_proc_bind(&mut self, host: impl AsRef<Path>, guest: impl AsRef<Path>) -> &mut Self {
+        self.add_fs_options(FsOptions::ProcBind {
+            source: host.as_ref().as_os_str().to_os_string(),
+            destination: guest.as_ref().as_os_str().to_os_string(),
+            permission: None,
+            try_: true,
+        })
+    }
+
     pub fn tmpfs(&mut self, path: impl AsRef<Path>) -> &mut Self {
         self.add_fs_options(FsOptions::TempFs {
             destination: path.as_ref().as_os_str().to_os_string(),
@@ -214,6 +791,84 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    /// Mount a tmpfs at `path` that can hold at most `size` bytes, writes past it fail with
+    /// `ENOSPC`.
+    ///
+    /// Since a tmpfs lives in memory, this bounds the memory the sandbox can use through it
+    pub fn tmpfs_with_size(&mut self, path: impl AsRef<Path>, size: usize) -> &mut Self {
+        self.add_fs_options(FsOptions::TempFs {
+            destination: path.as_ref().as_os_str().to_os_string(),
+            permission: None,
+            size: Some(size),
+        })
+    }
+
+    /// Mount a tmpfs of `size` bytes at `/dev/shm`, to be used by POSIX shared memory.
+    ///
+    /// This must be added after the `/dev` mount (see [`BwrapCommand::dev_dir`]) otherwise it
+    /// would be hidden by it.
+    pub fn dev_shm(&mut self, size: usize) -> &mut Self {
+        self.tmpfs_with_size("/dev/shm", size)
+    }
+
+    /// Create the runtime directory of the user `uid` (`/run/user/<uid>`) as a tmpfs only
+    /// accessible by its owner (mode `0700`), and point `XDG_RUNTIME_DIR` to it.
+    pub fn with_runtime_dir(&mut self, uid: u32) -> &mut Self {
+        let path = format!("/run/user/{uid}");
+        self.tmpfs(&path)
+            .chmod(&path, 0o700)
+            .add_env("XDG_RUNTIME_DIR", &path)
+    }
+
+    /// Give the sandbox access to `/dev/fuse`, so that it can mount FUSE filesystems.
+    ///
+    /// Mounting requires `CAP_SYS_ADMIN`, so the sandbox is put in its own user namespace (where
+    /// it can hold the capability without it meaning anything on the host) and is given the
+    /// capability. Do note that:
+    /// - bwrap refuses to add capabilities when it is setuid and not called by root, and the host
+    ///   must allow unprivileged user namespaces otherwise
+    /// - the kernel must allow FUSE mounts inside user namespaces (linux 4.18 or later)
+    /// - this must be added after the `/dev` mount (see [`BwrapCommand::dev_dir`]) otherwise it
+    ///   would be hidden by it
+    pub fn with_fuse(&mut self) -> &mut Self {
+        self.dev_bind("/dev/fuse", "/dev/fuse")
+            .add_namespace_flags(NsFlags::USER)
+            .cap_add("CAP_SYS_ADMIN")
+    }
+
+    /// Add a capability to the sandbox (`--cap-add`), `ALL` meaning every capability.
+    ///
+    /// The capabilities are added and dropped in the order of the calls, and adding the same
+    /// capability twice only adds it once (at the position of the last call). See
+    /// [`BwrapCommand::can_add_capabilities`] to know if bwrap will be allowed to add it.
+    pub fn cap_add(&mut self, cap: impl AsRef<OsStr>) -> &mut Self {
+        self.push_capability("--cap-add", cap.as_ref())
+    }
+
+    /// Drop a capability from the sandbox (`--cap-drop`), `ALL` meaning every capability.
+    ///
+    /// The capabilities are added and dropped in the order of the calls, and dropping the same
+    /// capability twice only drops it once (at the position of the last call).
+    pub fn cap_drop(&mut self, cap: impl AsRef<OsStr>) -> &mut Self {
+        self.push_capability("--cap-drop", cap.as_ref())
+    }
+
+    /// Drop every capability from the sandbox (`--cap-drop ALL`)
+    pub fn cap_drop_all(&mut self) -> &mut Self {
+        self.cap_drop("ALL")
+    }
+
+    /// Add `flag` for `cap` after the other capabilities.
+    ///
+    /// An identical earlier pair is removed instead of skipping the new one: only the last call
+    /// concerning a capability decides if it is kept, so it must stay last
+    fn push_capability(&mut self, flag: &'static str, cap: &OsStr) -> &mut Self {
+        self.capabilities
+            .retain(|(f, c)| !(*f == flag && c.as_os_str() == cap));
+        self.capabilities.push((flag, cap.to_os_string()));
+        self
+    }
+
     pub fn dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
         self.add_fs_options(FsOptions::Dir {
             destination: path.as_ref().as_os_str().to_os_string(),
@@ -221,6 +876,40 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    /// Change the permission of an existing path, only as seen from inside the sandbox.
+    ///
+    /// The options are applied in order, meaning that when the same path is changed multiple times
+    /// the last call wins.
+    pub fn chmod(&mut self, path: impl AsRef<Path>, permission: u64) -> &mut Self {
+        self.add_fs_options(FsOptions::Chmod {
+            destination: path.as_ref().as_os_str().to_os_string(),
+            permission,
+        })
+    }
+
+    /// Take a shared lock on `path` (as seen from inside the sandbox) while the sandbox is
+    /// running (`--lock-file`).
+    ///
+    /// This can be called multiple times to lock multiple files. The locks are taken in order with
+    /// the other filesystem options, so `path` must exist in the sandbox at that point (usually by
+    /// binding it first). This is useful to know which files are in use by running sandboxes, such
+    /// as the directories shared between multiple runs.
+    pub fn lock_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.add_fs_options(FsOptions::LockFile {
+            destination: path.as_ref().as_os_str().to_os_string(),
+        })
+    }
+
+    /// Remount `path` (as seen from inside the sandbox) read-only (`--remount-ro`), such as a
+    /// subdirectory of a writable bind.
+    ///
+    /// The options are applied in order, so this must be called after the bind it modifies.
+    pub fn remount_ro(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.add_fs_options(FsOptions::RemountRo {
+            destination: path.as_ref().as_os_str().to_os_string(),
+        })
+    }
+
     pub fn symlink(
         &mut self,
         source: impl AsRef<Path>,
@@ -232,6 +921,9 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    /// Copy the content read from `file` into a new file at `destination` (`--file`).
+    ///
+    /// See [`BwrapCommand::data`] to bind the content instead
     pub fn file(&mut self, file: &'fd impl AsFd, destination: impl AsRef<Path>) -> &mut Self {
         self.add_fs_options(FsOptions::File {
             destination: destination.as_ref().as_os_str().to_os_string(),
@@ -240,59 +932,541 @@ impl<'fd> BwrapCommand<'fd> {
         })
     }
 
+    /// Bind the directory referenced by `fd` at `destination` (`--bind-fd`).
+    ///
+    /// Unlike [`BwrapCommand::bind`] the source isn't resolved from a path when the sandbox is
+    /// set up, so the bind is exactly the directory that was opened. The same requirements as
+    /// [`BwrapCommand::seccomp`] apply to the fd.
+    pub fn bind_fd(&mut self, fd: &'fd impl AsFd, destination: impl AsRef<Path>) -> &mut Self {
+        self.add_fs_options(FsOptions::BindFd {
+            source_fd: fd.as_fd(),
+            destination: destination.as_ref().as_os_str().to_os_string(),
+            read_only: false,
+        })
+    }
+
+    /// Same as [`BwrapCommand::bind_fd`] but the bind is read-only (`--ro-bind-fd`)
+    pub fn bind_fd_read_only(
+        &mut self,
+        fd: &'fd impl AsFd,
+        destination: impl AsRef<Path>,
+    ) -> &mut Self {
+        self.add_fs_options(FsOptions::BindFd {
+            source_fd: fd.as_fd(),
+            destination: destination.as_ref().as_os_str().to_os_string(),
+            read_only: true,
+        })
+    }
+
+    /// Bind the content read from `file` at `destination`, the equivalent of `--bind-data`.
+    ///
+    /// Unlike [`BwrapCommand::file`] (which copies the content into a new file), this is a bind
+    /// mount of a file holding the data.
     pub fn data(&mut self, file: &'fd impl AsFd, destination: impl AsRef<Path>) -> &mut Self {
-        self.add_fs_options(FsOptions::File {
+        self.add_fs_options(FsOptions::Data {
             destination: destination.as_ref().as_os_str().to_os_string(),
             source: file.as_fd(),
             permission: None,
+            read_only: false,
         })
     }
-}
 
-impl<'fd> BwrapCommand<'fd> {
-    /// create an [`Vec<OsString>`] that will be the exact argument given to the bwrap binary
-    #[must_use]
-    pub fn build_args(&mut self) -> Vec<OsString> {
-        let mut v: Vec<OsString> = Vec::new();
-        if self.clear_env {
-            v.push(OsStr::new("--clearenv").to_os_string());
-        }
-        for (key, value) in &self.env {
-            v.push(OsStr::new("--setenv").to_os_string());
-            v.push(key.clone());
-            v.push(value.clone());
-        }
-        for key in &self.unset_env {
-            v.push(OsStr::new("--unsetenv").to_os_string());
-            v.push(key.clone());
-        }
-        for opts in &self.fs_options {
-            v.extend(opts.to_option());
-        }
-        v.extend(self.ns_options.to_options());
-        v.push(OsStr::new("--").to_os_string());
-        v.push(self.command.program.clone());
-        v.extend(self.command.args.clone());
-        v
+    /// Same as [`BwrapCommand::data`] but the bind is read-only (`--ro-bind-data`), meaning that
+    /// the sandbox can't write to it even if the permission of the file would allow it
+    pub fn ro_data(&mut self, file: &'fd impl AsFd, destination: impl AsRef<Path>) -> &mut Self {
+        self.add_fs_options(FsOptions::Data {
+            destination: destination.as_ref().as_os_str().to_os_string(),
+            source: file.as_fd(),
+            permission: None,
+            read_only: true,
+        })
     }
 
-    #[must_use = "This is only the description of the command\nIt must be used to launch the program"]
-    pub fn command(&mut self) -> std::process::Command {
-        let mut cmd = std::process::Command::new("bwrap");
-        cmd.args(self.build_args());
-        cmd
+    /// Read the command line from `fd` (`--args`) instead of giving it as arguments, which is useful
+    /// for very long command lines or ones containing arbitrary bytes.
+    ///
+    /// The content of the fd is NUL-separated arguments, read by bwrap as if they were given after
+    /// the other ones: it must contain `--` followed by the full argv of the program (including
+    /// `argv[0]`). This means that the program given to [`BwrapCommand::new`] is ignored, and it is
+    /// an error to also add arguments with [`BwrapCommand::arg`]. The same requirements as
+    /// [`BwrapCommand::seccomp`] apply to the fd.
+    pub fn args_fd(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.args_fd = Some(fd.as_fd());
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn env1() {
+    /// Load the seccomp filter read from `fd` in the sandbox (`--seccomp`), replacing the one
+    /// set by a previous call.
+    ///
+    /// The fd must contain a compiled BPF program. The caller is responsible for keeping it open
+    /// until the sandbox is spawned, and it must be inherited by bwrap (meaning without
+    /// `FD_CLOEXEC`, which Rust sets by default).
+    pub fn seccomp(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.seccomp = Some(fd.as_fd());
+        self
+    }
+
+    /// Add a seccomp filter read from `fd` to the sandbox (`--add-seccomp-fd`).
+    ///
+    /// Unlike [`BwrapCommand::seccomp`] this can be called multiple times, every filter is
+    /// loaded in the order of the calls. This requires bwrap 0.5.0 or later, and the same
+    /// requirements on the fd apply. bwrap refuses to mix it with [`BwrapCommand::seccomp`], so
+    /// building fails with [`BwrapBuildError::SeccompWithAddSeccompFd`] if both are set.
+    pub fn add_seccomp_fd(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.add_seccomp.push(fd.as_fd());
+        self
+    }
+
+    /// Give `fd` to bwrap, which keeps it open as long as the sandbox is running (`--sync-fd`).
+    ///
+    /// When `fd` is the write end of a pipe (and the caller closes its own copy after spawning),
+    /// the read end gets an EOF once the sandbox is torn down, which lets a supervising process
+    /// wait for it without polling. The same requirements as [`BwrapCommand::seccomp`] apply to
+    /// the fd.
+    pub fn sync_fd(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.sync_fd = Some(fd.as_fd());
+        self
+    }
+
+    /// Make bwrap write information about the sandbox on `fd` once it is set up (`--info-fd`).
+    ///
+    /// This is a JSON object containing the pid of the sandboxed process (as seen from outside of
+    /// the sandbox), which can be read with [`SandboxInfo::read`]. The same requirements as
+    /// [`BwrapCommand::seccomp`] apply to the fd.
+    pub fn info_fd(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.info_fd = Some(fd.as_fd());
+        self
+    }
+
+    /// Make bwrap stream the status of the sandbox on `fd` (`--json-status-fd`).
+    ///
+    /// Unlike [`BwrapCommand::info_fd`] this also reports the exit code of the program, which can
+    /// differ from the one of bwrap. The events can be read with [`BwrapStatus::stream`], and the
+    /// same requirements as [`BwrapCommand::seccomp`] apply to the fd.
+    pub fn json_status_fd(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.json_status_fd = Some(fd.as_fd());
+        self
+    }
+
+    /// Run the sandbox in the existing user namespace `fd` (`--userns`) instead of a new one,
+    /// such as the one of another sandbox.
+    ///
+    /// This can't be combined with [`NsFlags::USER`], [`NsFlags::USER_TRY`] or [`NsFlags::ALL`],
+    /// building the arguments fails if one of them is set. The same requirements as
+    /// [`BwrapCommand::seccomp`] apply to the fd.
+    pub fn userns(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.userns = Some(fd.as_fd());
+        self.ns_options.set_joins_userns(true);
+        self
+    }
+
+    /// Switch to the user namespace `fd` once the sandbox is set up (`--userns2`).
+    ///
+    /// This is useful to end up in a user namespace that was created before, while setting up
+    /// the sandbox with the privileges of a new one. The same requirements as
+    /// [`BwrapCommand::seccomp`] apply to the fd.
+    pub fn userns2(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.userns2 = Some(fd.as_fd());
+        self
+    }
+
+    /// Run the sandbox in the existing pid namespace `fd` (`--pidns`).
+    ///
+    /// With [`NsFlags::PID`] the new pid namespace is created inside of this one. The same
+    /// requirements as [`BwrapCommand::seccomp`] apply to the fd.
+    pub fn pidns(&mut self, fd: &'fd impl AsFd) -> &mut Self {
+        self.pidns = Some(fd.as_fd());
+        self
+    }
+
+    /// Make the sandboxed program see a fixed time using `libfaketime`.
+    ///
+    /// The library is bound read-only inside the sandbox and preloaded with `LD_PRELOAD` (after
+    /// the libraries already set with [`BwrapCommand::add_env`]), this means that it won't work
+    /// with statically linked programs.
+    ///
+    /// If `libfaketime` isn't installed on the host, or if `time` is before the UNIX epoch (which
+    /// `libfaketime` would read as an offset from the real time), this does nothing (except
+    /// logging a warning)
+    pub fn with_faketime(&mut self, time: std::time::SystemTime) -> &mut Self {
+        let library = FAKETIME_LIBRARIES
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists());
+        if library.is_none() {
+            log::warn!("libfaketime isn't installed, the sandbox will use the real time");
+        }
+        self.faketime_with_library(time, library)
+    }
+
+    fn faketime_with_library(
+        &mut self,
+        time: std::time::SystemTime,
+        library: Option<&Path>,
+    ) -> &mut Self {
+        let Some(library) = library else {
+            return self;
+        };
+        let Ok(seconds) = time.duration_since(std::time::UNIX_EPOCH) else {
+            log::warn!("faketime can't be before the epoch, the sandbox will use the real time");
+            return self;
+        };
+        let mut preload = OsString::new();
+        if let Some(existing) = self.env.get(OsStr::new("LD_PRELOAD")) {
+            preload.push(existing);
+            preload.push(":");
+        }
+        preload.push(library);
+        self.bind_read_only(library, library)
+            .add_env("LD_PRELOAD", preload)
+            .add_env("FAKETIME_FMT", "%s")
+            .add_env("FAKETIME", seconds.as_secs().to_string())
+    }
+}
+
+impl BwrapCommand<'_> {
+    /// Check if bwrap will be able to add the given capabilities to the sandbox.
+    ///
+    /// A setuid root bwrap only adds capabilities for a caller whose real uid is root. An
+    /// unprivileged bwrap adds them inside of a new user namespace ([`NsFlags::USER`], which
+    /// [`BwrapCommand::with_fuse`] relies on), otherwise the caller must have the capabilities.
+    /// bwrap fails at startup if it isn't allowed to add them.
+    /// The names are the ones given to `--cap-add` (`CAP_NET_ADMIN`, `ALL`, ...), an unknown name
+    /// always returns `false`.
+    #[must_use]
+    pub fn can_add_capabilities<S: AsRef<OsStr>>(&self, caps: impl IntoIterator<Item = S>) -> bool {
+        capabilities::can_add_capabilities(
+            self.bwrap.as_deref().unwrap_or(OsStr::new("bwrap")),
+            self.ns_options.sanitized_flags().contains(NsFlags::USER),
+            caps,
+        )
+    }
+
+    /// The environment variables set in the sandbox (`--setenv`).
+    ///
+    /// This doesn't include the `PATH` computed from [`BwrapCommand::add_to_path`]
+    #[must_use]
+    pub fn env(&self) -> &BTreeMap<OsString, OsString> {
+        &self.env
+    }
+
+    /// The environment variables removed from the sandbox (`--unsetenv`)
+    #[must_use]
+    pub fn unset_env(&self) -> &BTreeSet<OsString> {
+        &self.unset_env
+    }
+
+    /// The namespace flags, as set (they are only sanitized when building the arguments)
+    #[must_use]
+    pub fn namespace_flags(&self) -> NsFlags {
+        self.ns_options.flags
+    }
+
+    /// The working directory of the program inside the sandbox (`--chdir`)
+    #[must_use]
+    pub fn cwd(&self) -> Option<&Path> {
+        self.ns_options.get_cwd()
+    }
+
+    /// The program executed in the sandbox, with its arguments.
+    ///
+    /// See [`BwrapCommand::command`] for the command launching bwrap
+    #[must_use]
+    pub fn inner_command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Describe the filesystem of the sandbox, one line per option in the order they are applied
+    /// (for example `ro-bind /usr -> /usr` or `tmpfs -> /tmp (size 64M)`).
+    ///
+    /// This is meant to be read by humans (in logs or a run summary), see
+    /// [`BwrapCommand::build_args`] for the actual arguments
+    #[must_use]
+    pub fn describe_mounts(&self) -> Vec<String> {
+        self.fs_options.iter().map(FsOptions::describe).collect()
+    }
+
+    /// create an [`Vec<OsString>`] that will be the exact argument given to the bwrap binary
+    ///
+    /// This is the same as [`BwrapCommand::args_iter`], collected. The stored namespace flags
+    /// aren't modified, so building doesn't change the arguments of a later build
+    ///
+    /// # Errors
+    ///
+    /// This fails if one of the options can't be turned into valid arguments, see
+    /// [`BwrapBuildError`]
+    pub fn build_args(&self) -> Result<Vec<OsString>, BwrapBuildError> {
+        Ok(self.args_iter()?.map(Cow::into_owned).collect())
+    }
+
+    /// Check the command without building it.
+    ///
+    /// On top of the errors of [`BwrapCommand::build_args`], this rejects two different options
+    /// mounting something at the same destination
+    /// ([`BwrapBuildError::DuplicateDestination`]). bwrap allows it (the last mount hides the
+    /// previous ones), which is why building doesn't check it, but it is usually a mistake.
+    ///
+    /// # Errors
+    ///
+    /// see [`BwrapBuildError`] for the possible errors
+    pub fn validate(&self) -> Result<(), BwrapBuildError> {
+        self.check_args()?;
+        let mut mounts = BTreeMap::<&Path, &FsOptions>::new();
+        for option in &self.fs_options {
+            let Some(destination) = option.mount_destination() else {
+                continue;
+            };
+            match mounts.insert(Path::new(destination), option) {
+                // the exact same option twice is harmless
+                Some(first) if !first.to_option().into_iter().eq(option.to_option()) => {
+                    return Err(BwrapBuildError::DuplicateDestination {
+                        destination: destination.to_os_string(),
+                        first: first.describe(),
+                        second: option.describe(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the arguments can be built, see [`BwrapCommand::args_iter`]
+    fn check_args(&self) -> Result<(), BwrapBuildError> {
+        if !self.pending_overlay_src.is_empty() {
+            return Err(BwrapBuildError::DanglingOverlaySource {
+                sources: self.pending_overlay_src.clone(),
+            });
+        }
+        for opts in &self.fs_options {
+            opts.validate()?;
+        }
+        if self.userns.is_some()
+            && self
+                .ns_options
+                .sanitized_flags()
+                .intersects(NsFlags::USER | NsFlags::USER_TRY | NsFlags::ALL)
+        {
+            return Err(BwrapBuildError::UserNsWithUnshareUser);
+        }
+        if self.args_fd.is_some() && !self.command.args.is_empty() {
+            return Err(BwrapBuildError::ArgsWithArgsFd);
+        }
+        if self.seccomp.is_some() && !self.add_seccomp.is_empty() {
+            return Err(BwrapBuildError::SeccompWithAddSeccompFd);
+        }
+        Ok(())
+    }
+
+    /// The arguments given to the bwrap binary, without collecting them.
+    ///
+    /// The values stored in the command (such as the environment, or the program and its
+    /// arguments) are borrowed, only the computed ones (such as the fds, or the filesystem
+    /// options) are allocated. This is handy to give the arguments directly to
+    /// [`std::process::Command::args`].
+    ///
+    /// # Errors
+    ///
+    /// This fails if one of the options can't be turned into valid arguments, see
+    /// [`BwrapBuildError`]
+    pub fn args_iter(&self) -> Result<impl Iterator<Item = CowStr<'_>> + '_, BwrapBuildError> {
+        fn flag<'a>(flag: &'static str) -> CowStr<'a> {
+            Cow::Borrowed(OsStr::new(flag))
+        }
+        fn fd<'a>(fd: std::os::fd::BorrowedFd<'_>) -> CowStr<'a> {
+            Cow::Owned(fd.as_raw_fd().to_string().into())
+        }
+        fn with_fd<'a>(
+            (name, value): (&'static str, Option<std::os::fd::BorrowedFd<'_>>),
+        ) -> impl Iterator<Item = CowStr<'a>> {
+            value
+                .map(fd)
+                .into_iter()
+                .flat_map(move |value| [flag(name), value])
+        }
+        fn with_value<'a>(
+            (name, value): (&'static str, Option<&'a OsString>),
+        ) -> impl Iterator<Item = CowStr<'a>> {
+            value
+                .into_iter()
+                .flat_map(move |value| [flag(name), Cow::Borrowed(value.as_os_str())])
+        }
+
+        self.check_args()?;
+        let path = self.path_env();
+        let computed_path = path.is_some();
+        let env = self
+            .env
+            .iter()
+            .filter(move |(key, _)| !computed_path || *key != "PATH")
+            .flat_map(|(key, value)| {
+                [
+                    flag("--setenv"),
+                    Cow::Borrowed(key.as_os_str()),
+                    Cow::Borrowed(value.as_os_str()),
+                ]
+            });
+        let path = path
+            .into_iter()
+            .flat_map(|path| [flag("--setenv"), flag("PATH"), Cow::Owned(path)]);
+        // the environment is already empty with `--clearenv`
+        let unset_env = self
+            .unset_env
+            .iter()
+            .filter(|key| !self.clear_env && (self.path_dirs.is_empty() || *key != "PATH"))
+            .flat_map(|key| [flag("--unsetenv"), Cow::Borrowed(key.as_os_str())]);
+        let fs_options = self
+            .fs_options
+            .iter()
+            .flat_map(|opts| opts.to_option().into_iter().map(Cow::Owned));
+        let namespaces = [
+            ("--userns", self.userns),
+            ("--userns2", self.userns2),
+            ("--pidns", self.pidns),
+        ]
+        .into_iter()
+        .flat_map(with_fd);
+        let capabilities = self
+            .capabilities
+            .iter()
+            .flat_map(|(name, cap)| [flag(name), Cow::Borrowed(cap.as_os_str())]);
+        let seccomp = with_fd(("--seccomp", self.seccomp)).chain(
+            self.add_seccomp
+                .iter()
+                .flat_map(|&value| with_fd(("--add-seccomp-fd", Some(value)))),
+        );
+        let fds = [
+            ("--sync-fd", self.sync_fd),
+            ("--info-fd", self.info_fd),
+            ("--json-status-fd", self.json_status_fd),
+        ]
+        .into_iter()
+        .flat_map(with_fd);
+        let values = [
+            ("--exec-label", self.exec_label.as_ref()),
+            ("--file-label", self.file_label.as_ref()),
+            ("--argv0", self.argv0.as_ref()),
+        ]
+        .into_iter()
+        .flat_map(with_value);
+        // the command line is either read from the fd, or given after `--`
+        let command = self.args_fd.is_none().then(|| {
+            [OsStr::new("--"), self.command.program.as_os_str()]
+                .into_iter()
+                .chain(self.command.args.iter().map(OsString::as_os_str))
+                .map(Cow::Borrowed)
+        });
+
+        Ok(self
+            .clear_env
+            .then(|| flag("--clearenv"))
+            .into_iter()
+            .chain(env)
+            .chain(path)
+            .chain(unset_env)
+            .chain(fs_options)
+            .chain(self.ns_options.options().map(Cow::Owned))
+            .chain(namespaces)
+            .chain(capabilities)
+            .chain(seccomp)
+            .chain(fds)
+            .chain(values)
+            .chain(with_fd(("--args", self.args_fd)))
+            .chain(command.into_iter().flatten()))
+    }
+
+    /// Create the [`std::process::Command`] that will launch the sandbox, using the bwrap binary
+    /// set with [`BwrapCommand::bwrap`] (or `bwrap` from the `PATH` if unset)
+    ///
+    /// The stdio configured on the inner [`Command`] is moved into the returned command, meaning
+    /// that the following calls will inherit the stdio instead.
+    ///
+    /// # Errors
+    ///
+    /// This fails if the arguments can't be built, see [`BwrapCommand::build_args`]
+    pub fn command(&mut self) -> Result<std::process::Command, BwrapBuildError> {
+        let mut cmd =
+            std::process::Command::new(self.bwrap.as_deref().unwrap_or(OsStr::new("bwrap")));
+        cmd.args(self.build_args()?);
+        self.command.take_stdio(&mut cmd);
+        Ok(cmd)
+    }
+
+    /// Build the command and launch the sandbox, see [`BwrapCommand::command`]
+    ///
+    /// # Errors
+    ///
+    /// This fails if the arguments can't be built ([`std::io::ErrorKind::InvalidInput`], wrapping
+    /// the [`BwrapBuildError`]), or if bwrap can't be spawned
+    pub fn spawn(&mut self) -> std::io::Result<std::process::Child> {
+        self.command()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+            .spawn()
+    }
+
+    /// Launch the sandbox and wait for it to exit, see [`BwrapCommand::spawn`]
+    ///
+    /// # Errors
+    ///
+    /// This fails for the same reasons as [`BwrapCommand::spawn`], or if waiting fails
+    pub fn status(&mut self) -> std::io::Result<std::process::ExitStatus> {
+        self.spawn()?.wait()
+    }
+
+    /// Same as [`BwrapCommand::spawn`], giving back a [`tokio::process::Child`] that can be
+    /// awaited without blocking the runtime
+    ///
+    /// # Errors
+    ///
+    /// This fails for the same reasons as [`BwrapCommand::spawn`]
+    #[cfg(feature = "tokio")]
+    pub fn spawn_async(&mut self) -> std::io::Result<tokio::process::Child> {
+        let command = self
+            .command()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        tokio::process::Command::from(command).spawn()
+    }
+
+    /// Same as [`BwrapCommand::status`], waiting for the sandbox asynchronously
+    ///
+    /// # Errors
+    ///
+    /// This fails for the same reasons as [`BwrapCommand::status`]
+    #[cfg(feature = "tokio")]
+    pub async fn status_async(&mut self) -> std::io::Result<std::process::ExitStatus> {
+        self.spawn_async()?.wait().await
+    }
+
+    /// The command line launching the sandbox, quoted so that it can be copy-pasted in a shell
+    /// (for example `bwrap --ro-bind /usr /usr --setenv MSG 'hello world' -- ls`).
+    ///
+    /// This is meant for logs and debugging, the fds given to the sandbox (such as with
+    /// [`BwrapCommand::file`]) are only valid in the current process.
+    ///
+    /// # Errors
+    ///
+    /// This fails if the arguments can't be built, see [`BwrapCommand::build_args`]
+    pub fn to_shell_string(&self) -> Result<String, BwrapBuildError> {
+        let args = self.clone().build_args()?;
+        let bwrap = self.bwrap.as_deref().unwrap_or(OsStr::new("bwrap"));
+        Ok(std::iter::once(bwrap)
+            .chain(args.iter().map(OsString::as_os_str))
+            .map(shell::quote)
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn env1() {
         let args = crate::BwrapCommand::new("echo")
             .add_env("MY_ENV_VAR", "some value")
             .add_unset_env("PATH")
             .add_env("MY_ENV_VAR", "override")
-            .build_args();
+            .build_args()
+            .unwrap();
         assert_eq!(
             args,
             vec![
@@ -317,11 +1491,46 @@ mod tests {
             .add_unset_env("MANPAGER")
             .add_env("EDITOR", "nano")
             .remove_env("PAGER")
-            .build_args();
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec!["--clearenv", "--setenv", "EDITOR", "nano", "--", "echo"]
+        );
+    }
+    #[test]
+    fn envs_and_binds() {
+        let vars = std::collections::BTreeMap::from([("LANG", "C"), ("TERM", "dumb")]);
+        let args = crate::BwrapCommand::new("ls")
+            .envs(&vars)
+            .binds([("/srv/a", "/a"), ("/srv/b", "/b")])
+            .build_args()
+            .unwrap();
         assert_eq!(
             args,
             vec![
-                "--clearenv",
+                "--setenv", "LANG", "C", "--setenv", "TERM", "dumb", "--bind", "/srv/a", "/a",
+                "--bind", "/srv/b", "/b", "--", "ls"
+            ]
+        );
+    }
+    #[test]
+    fn unset_env_after_clear_env() {
+        let mut builder = crate::BwrapCommand::new("echo");
+        builder
+            .clear_env(true)
+            .add_unset_env("MANPAGER")
+            .add_env("EDITOR", "nano");
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec!["--clearenv", "--setenv", "EDITOR", "nano", "--", "echo"]
+        );
+        assert_eq!(builder.unset_env().len(), 1);
+
+        builder.clear_env(false);
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec![
                 "--setenv",
                 "EDITOR",
                 "nano",
@@ -333,10 +1542,42 @@ mod tests {
         );
     }
     #[test]
+    fn env_order() {
+        let args = crate::BwrapCommand::new("echo")
+            .add_env("ZED", "3")
+            .add_env("ALPHA", "1")
+            .add_env("MIDDLE", "2")
+            .add_unset_env("Z_UNSET")
+            .add_unset_env("A_UNSET")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--setenv",
+                "ALPHA",
+                "1",
+                "--setenv",
+                "MIDDLE",
+                "2",
+                "--setenv",
+                "ZED",
+                "3",
+                "--unsetenv",
+                "A_UNSET",
+                "--unsetenv",
+                "Z_UNSET",
+                "--",
+                "echo"
+            ]
+        );
+    }
+    #[test]
     fn cwd1() {
         let args = crate::BwrapCommand::new("echo")
             .set_cwd("/my/super/path")
-            .build_args();
+            .build_args()
+            .unwrap();
         assert_eq!(args, vec!["--chdir", "/my/super/path", "--", "echo"]);
     }
     #[test]
@@ -344,7 +1585,8 @@ mod tests {
         let args = crate::BwrapCommand::new("echo")
             .set_cwd("/my/super/path")
             .set_cwd("/my/better/path")
-            .build_args();
+            .build_args()
+            .unwrap();
         assert_eq!(args, vec!["--chdir", "/my/better/path", "--", "echo"]);
     }
     #[test]
@@ -353,7 +1595,1259 @@ mod tests {
             .set_cwd("/my/super/path")
             .set_cwd("/my/better/path")
             .unset_cwd()
-            .build_args();
+            .build_args()
+            .unwrap();
+        assert_eq!(args, vec!["--", "echo"]);
+    }
+    #[test]
+    fn worktree() {
+        let args = crate::BwrapCommand::new("echo")
+            .bind_worktree("/home/user/repo", "/repo")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--ro-bind",
+                "/home/user/repo",
+                "/repo",
+                "--tmpfs",
+                "/repo/.git",
+                "--",
+                "echo"
+            ]
+        );
+    }
+    #[test]
+    fn worktree_git_kind() {
+        let host = std::env::temp_dir().join(format!("kincir-worktree-{}", std::process::id()));
+        let args = |git_is_file: bool| {
+            std::fs::create_dir_all(&host).unwrap();
+            if git_is_file {
+                // a linked worktree
+                std::fs::write(
+                    host.join(".git"),
+                    "gitdir: /home/user/repo/.git/worktrees/wt\n",
+                )
+                .unwrap();
+            } else {
+                std::fs::create_dir(host.join(".git")).unwrap();
+            }
+            let args = crate::BwrapCommand::new("echo")
+                .bind_worktree(&host, "/repo")
+                .build_args()
+                .unwrap();
+            std::fs::remove_dir_all(&host).unwrap();
+            args
+        };
+        assert_eq!(args(false)[3..5], ["--tmpfs", "/repo/.git"]);
+        assert_eq!(args(true)[3..6], ["--ro-bind", "/dev/null", "/repo/.git"]);
+    }
+    #[test]
+    fn overlay() {
+        let args = crate::BwrapCommand::new("echo")
+            .overlay("/usr", ["/usr", "/opt/usr"], "/tmp/upper", "/tmp/work")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--overlay-src",
+                "/usr",
+                "--overlay-src",
+                "/opt/usr",
+                "--overlay",
+                "/tmp/upper",
+                "/tmp/work",
+                "/usr",
+                "--",
+                "echo"
+            ]
+        );
+        let args = crate::BwrapCommand::new("echo")
+            .overlay("/usr", [] as [&str; 0], "/tmp/upper", "/tmp/work")
+            .build_args()
+            .unwrap();
         assert_eq!(args, vec!["--", "echo"]);
     }
+    #[test]
+    fn ro_overlay() {
+        let args = crate::BwrapCommand::new("echo")
+            .ro_overlay("/usr", ["/usr", "/opt/usr"])
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--overlay-src",
+                "/usr",
+                "--overlay-src",
+                "/opt/usr",
+                "--ro-overlay",
+                "/usr",
+                "--",
+                "echo"
+            ]
+        );
+        let err = crate::BwrapCommand::new("echo")
+            .add_fs_options(crate::FsOptions::Overlay {
+                destination: "/usr".into(),
+                sources: vec!["/usr".into()],
+                rw: Some(("/tmp/upper".into(), "/tmp/work".into())),
+                read_only: true,
+            })
+            .build_args()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            crate::BwrapBuildError::ReadOnlyOverlayWithRw {
+                destination: "/usr".into()
+            }
+        );
+    }
+    #[test]
+    fn tmp_overlay() {
+        let args = crate::BwrapCommand::new("echo")
+            .tmp_overlay("/etc", ["/etc", "/srv/etc"])
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--overlay-src",
+                "/etc",
+                "--overlay-src",
+                "/srv/etc",
+                "--tmp-overlay",
+                "/etc",
+                "--",
+                "echo"
+            ]
+        );
+    }
+    #[test]
+    fn overlay_src() {
+        let args = crate::BwrapCommand::new("echo")
+            .overlay_src("/usr")
+            .overlay_src("/opt/usr")
+            .ro_overlay("/usr", ["/srv/usr"])
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--overlay-src",
+                "/usr",
+                "--overlay-src",
+                "/opt/usr",
+                "--overlay-src",
+                "/srv/usr",
+                "--ro-overlay",
+                "/usr",
+                "--",
+                "echo"
+            ]
+        );
+        let err = crate::BwrapCommand::new("echo")
+            .tmp_overlay("/usr", ["/usr"])
+            .overlay_src("/opt/usr")
+            .build_args()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            crate::BwrapBuildError::DanglingOverlaySource {
+                sources: vec!["/opt/usr".into()]
+            }
+        );
+    }
+    #[test]
+    fn proc_bind() {
+        let args = crate::BwrapCommand::new("echo")
+            .proc_bind("/proc", "/proc")
+            .try_proc_bind("/host/proc", "/proc")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--proc-bind",
+                "/proc",
+                "/proc",
+                "--proc-bind-try",
+                "/host/proc",
+                "/proc",
+                "--",
+                "echo"
+            ]
+        );
+    }
+    #[test]
+    fn mqueue() {
+        let args = crate::BwrapCommand::new("echo")
+            .mqueue("/dev/mqueue")
+            .build_args()
+            .unwrap();
+        assert_eq!(args, vec!["--mqueue", "/dev/mqueue", "--", "echo"]);
+    }
+    #[test]
+    fn chmod() {
+        let args = crate::BwrapCommand::new("echo")
+            .chmod("/etc/passwd", 0o600)
+            .chmod("/etc/passwd", 0o644)
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--chmod",
+                "600",
+                "/etc/passwd",
+                "--chmod",
+                "644",
+                "/etc/passwd",
+                "--",
+                "echo"
+            ]
+        );
+    }
+    #[test]
+    fn file_is_not_data() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let file_args = crate::BwrapCommand::new("cat")
+            .file(&file, "/etc/config")
+            .build_args()
+            .unwrap();
+        let data_args = crate::BwrapCommand::new("cat")
+            .data(&file, "/etc/config")
+            .build_args()
+            .unwrap();
+        assert_eq!(file_args[0], "--file");
+        assert_eq!(data_args[0], "--bind-data");
+        assert_eq!(file_args[1..], data_args[1..]);
+    }
+    #[test]
+    fn data() {
+        use std::os::fd::AsRawFd;
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let fd = file.as_raw_fd().to_string();
+        let args = crate::BwrapCommand::new("cat")
+            .data(&file, "/etc/config")
+            .ro_data(&file, "/etc/config.ro")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--bind-data",
+                &fd,
+                "/etc/config",
+                "--ro-bind-data",
+                &fd,
+                "/etc/config.ro",
+                "--",
+                "cat"
+            ]
+        );
+    }
+    #[test]
+    fn data_fd() {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+
+        // any readable fd works, such as a memfd or a temporary file
+        let path = std::env::temp_dir().join(format!("kincir-data-{}", std::process::id()));
+        let mut data = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data.write_all(b"key=value\n").unwrap();
+
+        let fd = data.as_raw_fd().to_string();
+        let args = crate::BwrapCommand::new("cat")
+            .data(&data, "/etc/rw.conf")
+            .ro_data(&data, "/etc/ro.conf")
+            .file(&data, "/etc/copy.conf")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--bind-data",
+                &fd,
+                "/etc/rw.conf",
+                "--ro-bind-data",
+                &fd,
+                "/etc/ro.conf",
+                "--file",
+                &fd,
+                "/etc/copy.conf",
+                "--",
+                "cat"
+            ]
+        );
+    }
+    #[test]
+    fn data_with_perm() {
+        use std::os::fd::{AsFd, AsRawFd};
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let fd = file.as_raw_fd().to_string();
+        for (read_only, flag) in [(false, "--bind-data"), (true, "--ro-bind-data")] {
+            let args = crate::BwrapCommand::new("cat")
+                .add_fs_options(crate::FsOptions::Data {
+                    source: file.as_fd(),
+                    destination: "/etc/config".into(),
+                    permission: Some(0o644),
+                    read_only,
+                })
+                .build_args()
+                .unwrap();
+            assert_eq!(
+                args,
+                vec!["--perms", "644", flag, &fd, "/etc/config", "--", "cat"]
+            );
+        }
+    }
+    #[test]
+    fn runtime_dir() {
+        let args = crate::BwrapCommand::new("echo")
+            .with_runtime_dir(1000)
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--setenv",
+                "XDG_RUNTIME_DIR",
+                "/run/user/1000",
+                "--tmpfs",
+                "/run/user/1000",
+                "--chmod",
+                "700",
+                "/run/user/1000",
+                "--",
+                "echo"
+            ]
+        );
+    }
+    #[test]
+    fn multicall() {
+        let args = crate::BwrapCommand::multicall("/bin/busybox", "sh", ["-c", "true"])
+            .build_args()
+            .unwrap();
+        assert_eq!(args[..3], ["--ro-bind", "/bin/busybox", "/bin/busybox"]);
+        assert_eq!(
+            args[3..],
+            ["--argv0", "sh", "--", "/bin/busybox", "-c", "true"]
+        );
+    }
+    #[test]
+    fn bind_with_perm() {
+        let args = crate::BwrapCommand::new("ls")
+            .bind_with_perm("/srv", "/srv", 0o755)
+            .dev_bind_with_perm("/dev/null", "/dev/null", 0o666)
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--perms",
+                "755",
+                "--bind",
+                "/srv",
+                "/srv",
+                "--perms",
+                "666",
+                "--dev-bind",
+                "/dev/null",
+                "/dev/null",
+                "--",
+                "ls"
+            ]
+        );
+        let args = crate::BwrapCommand::new("ls")
+            .bind_read_only_with_perm("/srv", "/srv", 0o555)
+            .build_args()
+            .unwrap();
+        assert_eq!(args[..2], ["--perms", "555"]);
+    }
+    #[test]
+    fn perms_and_size() {
+        use crate::FsOptions;
+
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .add_fs_options(FsOptions::Perms { perm: 0o700 })
+            .add_fs_options(FsOptions::SetSize { size: 1 << 20 })
+            .tmpfs("/scratch")
+            .add_fs_options(FsOptions::Perms { perm: 0o1777 })
+            .dir("/shared");
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec![
+                "--perms", "700", "--size", "1048576", "--tmpfs", "/scratch", "--perms", "1777",
+                "--dir", "/shared", "--", "ls"
+            ]
+        );
+        assert_eq!(
+            builder.describe_mounts()[..2],
+            [
+                "perms 700 (for the next option)",
+                "size 1M (for the next option)"
+            ]
+        );
+    }
+    #[test]
+    fn tmpfs_with_size() {
+        let args = crate::BwrapCommand::new("ls")
+            .tmpfs_with_size("/tmp", 4096)
+            .build_args()
+            .unwrap();
+        assert_eq!(args, vec!["--size", "4096", "--tmpfs", "/tmp", "--", "ls"]);
+    }
+    #[test]
+    fn bind_flags() {
+        let args = crate::BwrapCommand::new("ls")
+            .bind("/a", "/a")
+            .try_bind("/b", "/b")
+            .bind_read_only("/c", "/c")
+            .try_bind_ready_only("/d", "/d")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--bind",
+                "/a",
+                "/a",
+                "--bind-try",
+                "/b",
+                "/b",
+                "--ro-bind",
+                "/c",
+                "/c",
+                "--ro-bind-try",
+                "/d",
+                "/d",
+                "--",
+                "ls"
+            ]
+        );
+    }
+    #[test]
+    fn bwrap_path() {
+        let mut builder = crate::BwrapCommand::new("ls");
+        assert_eq!(builder.command().unwrap().get_program(), "bwrap");
+        builder.bwrap(Some("/nix/store/bubblewrap/bin/bwrap"));
+        assert_eq!(
+            builder.command().unwrap().get_program(),
+            "/nix/store/bubblewrap/bin/bwrap"
+        );
+    }
+    #[test]
+    fn stdio() {
+        use std::io::Read;
+
+        let mut inner = crate::Command::new("ls");
+        inner.stdout(std::process::Stdio::piped());
+        let mut builder = crate::BwrapCommand::new(inner);
+        // `echo` prints the arguments that bwrap would have been given
+        builder.bwrap(Some("echo"));
+        let mut child = builder.command().unwrap().spawn().unwrap();
+        let mut stdout = String::new();
+        child
+            .stdout
+            .take()
+            .expect("stdout should be piped")
+            .read_to_string(&mut stdout)
+            .unwrap();
+        child.wait().unwrap();
+        assert_eq!(stdout, "-- ls\n");
+    }
+    #[test]
+    fn fuse() {
+        let args = crate::BwrapCommand::new("sshfs")
+            .dev_dir("/dev")
+            .with_fuse()
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--dev",
+                "/dev",
+                "--dev-bind",
+                "/dev/fuse",
+                "/dev/fuse",
+                "--unshare-user",
+                "--cap-add",
+                "CAP_SYS_ADMIN",
+                "--",
+                "sshfs"
+            ]
+        );
+    }
+    #[test]
+    fn seccomp() {
+        use std::os::fd::AsRawFd;
+
+        let filter = std::fs::File::open("/dev/null").unwrap();
+        let args = crate::BwrapCommand::new("ls")
+            .seccomp(&filter)
+            .build_args()
+            .unwrap();
+        let fd = filter.as_raw_fd().to_string();
+        assert_eq!(args, vec!["--seccomp", &fd, "--", "ls"]);
+    }
+    #[test]
+    fn sync_fd() {
+        use std::os::fd::AsRawFd;
+
+        let sync = std::fs::File::open("/dev/null").unwrap();
+        let args = crate::BwrapCommand::new("ls")
+            .sync_fd(&sync)
+            .build_args()
+            .unwrap();
+        let fd = sync.as_raw_fd().to_string();
+        assert_eq!(args, vec!["--sync-fd", &fd, "--", "ls"]);
+    }
+    #[test]
+    fn info_fd() {
+        use std::os::fd::AsRawFd;
+
+        let info = std::fs::File::open("/dev/null").unwrap();
+        let args = crate::BwrapCommand::new("ls")
+            .info_fd(&info)
+            .build_args()
+            .unwrap();
+        let fd = info.as_raw_fd().to_string();
+        assert_eq!(args, vec!["--info-fd", &fd, "--", "ls"]);
+    }
+    #[test]
+    fn json_status_fd() {
+        use std::os::fd::AsRawFd;
+
+        let status = std::fs::File::open("/dev/null").unwrap();
+        let args = crate::BwrapCommand::new("ls")
+            .json_status_fd(&status)
+            .build_args()
+            .unwrap();
+        let fd = status.as_raw_fd().to_string();
+        assert_eq!(args, vec!["--json-status-fd", &fd, "--", "ls"]);
+    }
+    #[test]
+    fn join_namespaces() {
+        use std::os::fd::AsRawFd;
+
+        let userns = std::fs::File::open("/dev/null").unwrap();
+        let pidns = std::fs::File::open("/dev/null").unwrap();
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .userns(&userns)
+            .pidns(&pidns)
+            .add_namespace_flags(crate::NsFlags::PID);
+        builder.uid(0);
+        let userns = userns.as_raw_fd().to_string();
+        let pidns = pidns.as_raw_fd().to_string();
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec![
+                "--unshare-pid",
+                "--uid",
+                "0",
+                "--userns",
+                &userns,
+                "--pidns",
+                &pidns,
+                "--",
+                "ls"
+            ]
+        );
+
+        builder.add_namespace_flags(crate::NsFlags::USER_TRY);
+        assert_eq!(
+            builder.build_args(),
+            Err(crate::BwrapBuildError::UserNsWithUnshareUser)
+        );
+    }
+    #[test]
+    fn add_seccomp_fd() {
+        use std::os::fd::AsRawFd;
+
+        let filters = [(); 3].map(|()| std::fs::File::open("/dev/null").unwrap());
+        let mut builder = crate::BwrapCommand::new("ls");
+        for filter in &filters {
+            builder.add_seccomp_fd(filter);
+        }
+        let args = builder.build_args().unwrap();
+        let fds = filters.each_ref().map(|filter| filter.as_raw_fd().to_string());
+        assert_eq!(
+            args,
+            vec![
+                "--add-seccomp-fd",
+                &fds[0],
+                "--add-seccomp-fd",
+                &fds[1],
+                "--add-seccomp-fd",
+                &fds[2],
+                "--",
+                "ls"
+            ]
+        );
+
+        builder.seccomp(&filters[0]);
+        assert_eq!(
+            builder.build_args(),
+            Err(crate::BwrapBuildError::SeccompWithAddSeccompFd)
+        );
+    }
+    #[test]
+    fn capabilities() {
+        let args = crate::BwrapCommand::new("ping")
+            .cap_drop_all()
+            .cap_add("CAP_NET_RAW")
+            .cap_drop_all()
+            .cap_add("CAP_NET_RAW")
+            .cap_drop("CAP_CHOWN")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--cap-drop",
+                "ALL",
+                "--cap-add",
+                "CAP_NET_RAW",
+                "--cap-drop",
+                "CAP_CHOWN",
+                "--",
+                "ping"
+            ]
+        );
+    }
+    #[test]
+    fn capabilities_last_call_wins() {
+        let args = crate::BwrapCommand::new("ping")
+            .cap_add("CAP_NET_RAW")
+            .cap_drop("CAP_NET_RAW")
+            .cap_add("CAP_NET_RAW")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--cap-drop",
+                "CAP_NET_RAW",
+                "--cap-add",
+                "CAP_NET_RAW",
+                "--",
+                "ping"
+            ]
+        );
+    }
+    #[test]
+    fn describe_mounts() {
+        let mounts = crate::BwrapCommand::new("ls")
+            .bind_read_only("/usr", "/usr")
+            .try_bind("/srv", "/srv")
+            .tmpfs_with_size("/tmp", 64 << 20)
+            .dev_dir("/dev")
+            .bind_with_perm("/data", "/data", 0o755)
+            .symlink("usr/bin", "/bin")
+            .tmp_overlay("/opt", ["/opt", "/opt2"])
+            .chmod("/tmp", 0o1777)
+            .describe_mounts();
+        assert_eq!(
+            mounts,
+            [
+                "ro-bind /usr -> /usr",
+                "bind-try /srv -> /srv",
+                "tmpfs -> /tmp (size 64M)",
+                "dev -> /dev",
+                "bind /data -> /data (mode 755)",
+                "symlink usr/bin -> /bin",
+                "tmp-overlay /opt + /opt2 -> /opt",
+                "chmod /tmp (mode 1777)",
+            ]
+        );
+    }
+    #[test]
+    fn add_to_path() {
+        let args = crate::BwrapCommand::new("cc")
+            .add_env("PATH", "/usr/bin")
+            .bind_to_path("/opt/toolchain/bin", "/opt/toolchain/bin")
+            .add_to_path("/opt/tools")
+            .add_to_path("/opt/tools")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--setenv",
+                "PATH",
+                "/usr/bin:/opt/toolchain/bin:/opt/tools",
+                "--ro-bind",
+                "/opt/toolchain/bin",
+                "/opt/toolchain/bin",
+                "--",
+                "cc"
+            ]
+        );
+
+        let args = crate::BwrapCommand::new("cc")
+            .clear_env(true)
+            .add_to_path("/opt/tools")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec!["--clearenv", "--setenv", "PATH", "/opt/tools", "--", "cc"]
+        );
+    }
+    #[test]
+    fn args_fd() {
+        use std::os::fd::AsRawFd;
+
+        let argv = std::fs::File::open("/dev/null").unwrap();
+        let fd = argv.as_raw_fd().to_string();
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder.bind_read_only("/usr", "/usr").args_fd(&argv);
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec!["--ro-bind", "/usr", "/usr", "--args", &fd]
+        );
+        builder.arg("-l");
+        assert_eq!(
+            builder.build_args(),
+            Err(crate::BwrapBuildError::ArgsWithArgsFd)
+        );
+    }
+    #[test]
+    fn argv0() {
+        let mut command = crate::BwrapCommand::new("/bin/bash");
+        command.argv0("-bash").arg("-c").arg("true");
+        let args = command.build_args().unwrap();
+        assert_eq!(
+            args[args.len() - 6..],
+            ["--argv0", "-bash", "--", "/bin/bash", "-c", "true"]
+        );
+    }
+
+    #[test]
+    fn lock_file() {
+        let args = crate::BwrapCommand::new("ls")
+            .lock_file("/first")
+            .bind_read_only("/srv/deps", "/deps")
+            .lock_file("/deps/.lock")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--lock-file",
+                "/first",
+                "--ro-bind",
+                "/srv/deps",
+                "/deps",
+                "--lock-file",
+                "/deps/.lock",
+                "--",
+                "ls"
+            ]
+        );
+    }
+
+    #[test]
+    fn as_pid_1() {
+        let mut builder = crate::BwrapCommand::new("init");
+        builder.as_pid_1(true);
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec!["--unshare-pid", "--as-pid-1", "--", "init"]
+        );
+        builder.as_pid_1(false);
+        assert_eq!(builder.build_args().unwrap(), vec!["--", "init"]);
+    }
+
+    #[test]
+    fn session_flags() {
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder.new_session(true).die_with_parent(true);
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec!["--die-with-parent", "--new-session", "--", "ls"]
+        );
+        builder.die_with_parent(false);
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec!["--new-session", "--", "ls"]
+        );
+    }
+
+    #[test]
+    fn remount_ro() {
+        let args = crate::BwrapCommand::new("ls")
+            .bind("/srv/work", "/work")
+            .remount_ro("/work/config")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--bind",
+                "/srv/work",
+                "/work",
+                "--remount-ro",
+                "/work/config",
+                "--",
+                "ls"
+            ]
+        );
+    }
+
+    #[test]
+    fn selinux_labels() {
+        let mut builder = crate::BwrapCommand::new("ls");
+        assert_eq!(builder.build_args().unwrap(), vec!["--", "ls"]);
+        builder
+            .exec_label("system_u:system_r:container_t:s0")
+            .file_label("system_u:object_r:container_file_t:s0");
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec![
+                "--exec-label",
+                "system_u:system_r:container_t:s0",
+                "--file-label",
+                "system_u:object_r:container_file_t:s0",
+                "--",
+                "ls"
+            ]
+        );
+    }
+
+    #[test]
+    fn clone() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let mut template = crate::BwrapCommand::new("cat");
+        template
+            .bind_read_only("/usr", "/usr")
+            .file(&file, "/etc/config")
+            .add_env("LANG", "C")
+            .add_namespace_flags(crate::NsFlags::NET);
+        let mut run = template.clone();
+        assert_eq!(run.build_args(), template.build_args());
+
+        run.arg("/etc/config");
+        assert_ne!(run.build_args(), template.build_args());
+    }
+
+    #[test]
+    fn shell_string() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .bind_read_only("/usr", "/usr")
+            .add_env("MSG", "hello world")
+            .arg(std::ffi::OsStr::from_bytes(b"caf\xe9"));
+        assert_eq!(
+            builder.to_shell_string().unwrap(),
+            r"bwrap --setenv MSG 'hello world' --ro-bind /usr /usr -- ls $'caf\xe9'"
+        );
+        builder.bwrap(Some("/opt/bwrap/bin/bwrap"));
+        assert!(builder
+            .to_shell_string()
+            .unwrap()
+            .starts_with("/opt/bwrap/bin/bwrap --setenv"));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        use std::os::fd::AsRawFd;
+
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .bind_read_only("/usr", "/usr")
+            .tmpfs_with_size("/tmp", 1 << 20)
+            .add_env("LANG", "C")
+            .cap_drop_all()
+            .cap_add("CAP_NET_RAW")
+            .add_namespace_flags(crate::NsFlags::NET | crate::NsFlags::PID)
+            .set_cwd("/tmp")
+            .arg("-l");
+        let json = serde_json::to_string(&builder).unwrap();
+        let restored: crate::BwrapCommand = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.build_args(), builder.build_args());
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        builder.file(&file, "/etc/config");
+        let json = serde_json::to_value(&builder).unwrap();
+        assert_eq!(json["fs_options"][2]["File"]["source"], file.as_raw_fd());
+        let err = serde_json::from_value::<crate::BwrapCommand>(json).unwrap_err();
+        assert!(err.to_string().contains("fds can't be deserialized"));
+    }
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_rejects_fds() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder.seccomp(&file);
+        let json = serde_json::to_string(&builder).unwrap();
+        let err = serde_json::from_str::<crate::BwrapCommand>(&json).unwrap_err();
+        assert!(err.to_string().contains("fds can't be deserialized"));
+
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder.add_seccomp_fd(&file).info_fd(&file);
+        let json = serde_json::to_string(&builder).unwrap();
+        assert!(serde_json::from_str::<crate::BwrapCommand>(&json).is_err());
+
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder.data(&file, "/etc/config");
+        let json = serde_json::to_string(&builder).unwrap();
+        assert!(serde_json::from_str::<crate::BwrapCommand>(&json).is_err());
+    }
+
+    #[test]
+    fn reset_fs_options() {
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .bind_read_only("/usr", "/usr")
+            .tmpfs("/tmp")
+            .overlay_src("/layer");
+        assert_eq!(builder.fs_options().len(), 2);
+
+        builder.clear_fs_options();
+        assert!(builder.fs_options().is_empty());
+        assert_eq!(builder.build_args().unwrap(), vec!["--", "ls"]);
+
+        builder.set_fs_options(vec![crate::FsOptions::Dir {
+            destination: "/work".into(),
+            permission: None,
+        }]);
+        builder.dir("/out");
+        assert_eq!(
+            builder.build_args().unwrap(),
+            vec!["--dir", "/work", "--dir", "/out", "--", "ls"]
+        );
+    }
+
+    #[test]
+    fn getters() {
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .add_env("LANG", "C")
+            .add_unset_env("PAGER")
+            .add_namespace_flags(crate::NsFlags::NET)
+            .set_cwd("/work")
+            .arg("-l");
+        assert_eq!(
+            builder.env().get(std::ffi::OsStr::new("LANG")),
+            Some(&"C".into())
+        );
+        assert!(builder.unset_env().contains(std::ffi::OsStr::new("PAGER")));
+        assert_eq!(builder.namespace_flags(), crate::NsFlags::NET);
+        assert_eq!(builder.cwd(), Some(std::path::Path::new("/work")));
+        assert_eq!(builder.inner_command().get_program(), "ls");
+        assert_eq!(builder.inner_command().get_args(), ["-l"]);
+    }
+
+    #[test]
+    fn identity() {
+        let args = crate::BwrapCommand::new("id")
+            .hostname("sandbox")
+            .uid(1000)
+            .gid(100)
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--unshare-user",
+                "--unshare-uts",
+                "--gid",
+                "100",
+                "--uid",
+                "1000",
+                "--hostname",
+                "sandbox",
+                "--",
+                "id"
+            ]
+        );
+    }
+
+    #[test]
+    fn bind_fd() {
+        use std::os::fd::AsRawFd;
+
+        let dir = std::fs::File::open("/usr").unwrap();
+        let fd = dir.as_raw_fd().to_string();
+        let args = crate::BwrapCommand::new("ls")
+            .bind_fd(&dir, "/rw")
+            .bind_fd_read_only(&dir, "/ro")
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--bind-fd",
+                &fd,
+                "/rw",
+                "--ro-bind-fd",
+                &fd,
+                "/ro",
+                "--",
+                "ls"
+            ]
+        );
+    }
+
+    #[test]
+    fn args_iter() {
+        use std::borrow::Cow;
+
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .clear_env(true)
+            .add_env("LANG", "C")
+            .add_to_path("/usr/bin")
+            .bind_read_only("/usr", "/usr")
+            .add_namespace_flags(crate::NsFlags::NET)
+            .argv0("list")
+            .arg("-l");
+        let iterated = builder
+            .args_iter()
+            .unwrap()
+            .map(Cow::into_owned)
+            .collect::<Vec<_>>();
+        assert_eq!(iterated, builder.build_args().unwrap());
+
+        let args = builder.args_iter().unwrap().collect::<Vec<_>>();
+        let tail = &args[args.len() - 3..];
+        for (arg, expected) in tail.iter().zip(["--", "ls", "-l"]) {
+            assert!(matches!(arg, Cow::Borrowed(arg) if *arg == expected));
+        }
+    }
+
+    #[test]
+    fn non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // "café" in latin-1
+        let path = OsStr::from_bytes(b"/srv/caf\xe9");
+        let args = crate::BwrapCommand::new(OsStr::from_bytes(b"./caf\xe9"))
+            .bind_read_only(path, path)
+            .symlink(path, "/link")
+            .tmp_overlay("/mnt", [path])
+            .set_cwd(path)
+            .hostname(OsStr::from_bytes(b"h\xf4te"))
+            .add_env("DIR", path)
+            .arg(path)
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                OsStr::new("--setenv"),
+                OsStr::new("DIR"),
+                path,
+                OsStr::new("--ro-bind"),
+                path,
+                path,
+                OsStr::new("--symlink"),
+                path,
+                OsStr::new("/link"),
+                OsStr::new("--overlay-src"),
+                path,
+                OsStr::new("--tmp-overlay"),
+                OsStr::new("/mnt"),
+                OsStr::new("--unshare-uts"),
+                OsStr::new("--hostname"),
+                OsStr::from_bytes(b"h\xf4te"),
+                OsStr::new("--chdir"),
+                path,
+                OsStr::new("--"),
+                OsStr::from_bytes(b"./caf\xe9"),
+                path,
+            ]
+        );
+    }
+
+    #[test]
+    fn spawn() {
+        // `true` ignores the bwrap arguments, so this only checks the launching
+        let status = crate::BwrapCommand::new("ls")
+            .bwrap(Some("true"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut builder = crate::BwrapCommand::new("ls");
+        let child = builder.bwrap(Some("/nonexistent/bwrap")).spawn();
+        assert_eq!(child.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+        let error = crate::BwrapCommand::new("ls")
+            .bwrap(Some("true"))
+            .overlay_src("/usr")
+            .spawn()
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let error = error.into_inner().unwrap();
+        assert_eq!(
+            *error.downcast::<crate::BwrapBuildError>().unwrap(),
+            crate::BwrapBuildError::DanglingOverlaySource {
+                sources: vec!["/usr".into()]
+            }
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "tokio")]
+    async fn spawn_async() {
+        let status = crate::BwrapCommand::new("ls")
+            .bwrap(Some("false"))
+            .status_async()
+            .await
+            .unwrap();
+        assert_eq!(status.code(), Some(1));
+
+        let mut inner = crate::Command::new("ls");
+        inner.stdout(std::process::Stdio::piped());
+        let mut builder = crate::BwrapCommand::new(inner);
+        // `echo` prints the arguments that bwrap would have been given
+        builder.bwrap(Some("echo")).add_env("MSG", "hello");
+        let output = builder
+            .spawn_async()
+            .unwrap()
+            .wait_with_output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"--setenv MSG hello -- ls\n");
+    }
+
+    #[test]
+    fn validate() {
+        let mut builder = crate::BwrapCommand::new("ls");
+        builder
+            .dir("/opt")
+            .bind_read_only("/usr", "/usr/")
+            .chmod("/usr", 0o755)
+            .bind_read_only("/usr", "/usr/")
+            .tmpfs("/opt");
+        assert_eq!(builder.validate(), Ok(()));
+
+        builder.bind("/srv/usr", "/usr");
+        assert_eq!(
+            builder.validate(),
+            Err(crate::BwrapBuildError::DuplicateDestination {
+                destination: "/usr".into(),
+                first: "ro-bind /usr -> /usr/".into(),
+                second: "bind /srv/usr -> /usr".into(),
+            })
+        );
+        // bwrap accepts it, so building doesn't check it
+        assert!(builder.build_args().is_ok());
+
+        builder.overlay_src("/lower");
+        assert_eq!(
+            builder.validate(),
+            Err(crate::BwrapBuildError::DanglingOverlaySource {
+                sources: vec!["/lower".into()]
+            })
+        );
+    }
+
+    #[test]
+    fn dev_shm() {
+        let args = crate::BwrapCommand::new("echo")
+            .dev_dir("/dev")
+            .dev_shm(64 << 20)
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec!["--dev", "/dev", "--size", "67108864", "--tmpfs", "/dev/shm", "--", "echo"]
+        );
+    }
+    #[test]
+    fn faketime() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        let args = crate::BwrapCommand::new("date")
+            .faketime_with_library(time, Some("/lib/libfaketime.so.1".as_ref()))
+            .build_args()
+            .unwrap();
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--setenv", "FAKETIME", "1000000000"]));
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--setenv", "FAKETIME_FMT", "%s"]));
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--setenv", "LD_PRELOAD", "/lib/libfaketime.so.1"]));
+
+        let args = crate::BwrapCommand::new("date")
+            .faketime_with_library(time, None)
+            .build_args()
+            .unwrap();
+        assert_eq!(args, vec!["--", "date"]);
+
+        // `-N` would be an offset from the real time
+        let before_epoch = std::time::UNIX_EPOCH - std::time::Duration::from_secs(1);
+        let args = crate::BwrapCommand::new("date")
+            .faketime_with_library(before_epoch, Some("/lib/libfaketime.so.1".as_ref()))
+            .build_args()
+            .unwrap();
+        assert_eq!(args, vec!["--", "date"]);
+    }
+    #[test]
+    fn faketime_existing_preload() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        let args = crate::BwrapCommand::new("date")
+            .add_env("LD_PRELOAD", "/lib/libother.so")
+            .faketime_with_library(time, Some("/lib/libfaketime.so.1".as_ref()))
+            .build_args()
+            .unwrap();
+        assert!(args.windows(3).any(|w| w
+            == [
+                "--setenv",
+                "LD_PRELOAD",
+                "/lib/libother.so:/lib/libfaketime.so.1"
+            ]));
+    }
+    #[test]
+    #[ignore = "requires bwrap and libfaketime"]
+    fn faketime_date() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        let output = crate::BwrapCommand::new("date")
+            .bind_read_only("/", "/")
+            .arg("+%s")
+            .with_faketime(time)
+            .command()
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"1000000000\n");
+    }
+    #[test]
+    #[ignore = "requires bwrap and /dev/fuse"]
+    fn fuse_device() {
+        let output = crate::BwrapCommand::new("test")
+            .bind_read_only("/", "/")
+            .dev_dir("/dev")
+            .with_fuse()
+            .arg("-c")
+            .arg("/dev/fuse")
+            .command()
+            .unwrap()
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+    }
 }
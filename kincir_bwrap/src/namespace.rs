@@ -5,12 +5,16 @@ use std::{
 };
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NsOptions {
     pub flags: NsFlags,
     gid: Option<std::ffi::c_int>,
     uid: Option<std::ffi::c_int>,
     hostname: Option<OsString>,
     cwd: Option<PathBuf>,
+    /// Set when an existing user namespace is joined (`--userns`), in which case setting the
+    /// uid/gid doesn't need a new one
+    joins_userns: bool,
 }
 
 impl NsOptions {
@@ -27,6 +31,11 @@ impl NsOptions {
         self.cwd = None;
     }
 
+    #[must_use]
+    pub fn get_cwd(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
     pub fn set_hostname(&mut self, hostname: impl AsRef<OsStr>) {
         self.hostname = Some(hostname.as_ref().into());
     }
@@ -63,6 +72,10 @@ impl NsOptions {
     pub fn unset_gid(&mut self) {
         self.gid = None;
     }
+
+    pub(crate) fn set_joins_userns(&mut self, joins_userns: bool) {
+        self.joins_userns = joins_userns;
+    }
 }
 
 impl NsOptions {
@@ -71,23 +84,36 @@ impl NsOptions {
         Self::default()
     }
 
-    pub fn sanitize_flags(&mut self) {
-        if self.gid.is_some() {
-            self.flags.set(NsFlags::USER, true);
+    /// The flags as they will be given to bwrap, without modifying the stored ones (see
+    /// [`NsOptions::sanitize_flags`])
+    #[must_use]
+    pub fn sanitized_flags(&self) -> NsFlags {
+        let mut flags = self.flags;
+        if self.gid.is_some() && !self.joins_userns {
+            flags.set(NsFlags::USER, true);
         }
-        if self.uid.is_some() {
-            self.flags.set(NsFlags::USER, true);
+        if self.uid.is_some() && !self.joins_userns {
+            flags.set(NsFlags::USER, true);
         }
         if self.hostname.is_some() {
-            self.flags.set(NsFlags::UTS, true);
+            flags.set(NsFlags::UTS, true);
         }
 
-        self.flags = self.flags.sanitize();
+        flags.sanitize_with(self.joins_userns)
+    }
+
+    pub fn sanitize_flags(&mut self) {
+        self.flags = self.sanitized_flags();
     }
 
     pub fn to_options(&mut self) -> impl Iterator<Item = OsString> {
         self.sanitize_flags();
-        let mut v = self.flags.to_options().collect::<Vec<_>>();
+        self.options()
+    }
+
+    /// Same as [`NsOptions::to_options`], without modifying the stored flags
+    pub fn options(&self) -> impl Iterator<Item = OsString> {
+        let mut v = self.sanitized_flags().to_options().collect::<Vec<_>>();
 
         if let Some(&gid) = self.gid.as_ref() {
             v.push(OsString::from("--gid"));
@@ -118,8 +144,32 @@ impl NsFlags {
     /// - the `_TRY` variants are removed if the non `_TRY` variants are present
     ///
     /// - all the `--unshare-*` bwrap flags are removed if the [`NsFlags::ALL`] is present
+    ///
+    /// - [`NsFlags::PID`] is added if [`NsFlags::AS_PID_1`] is present, since the program can
+    ///   only be pid 1 of a new pid namespace
+    ///
+    /// - [`NsFlags::SHARE_NET`] is removed if [`NsFlags::NET`] is present: asking for both is
+    ///   contradictory, and the network stays isolated (even with [`NsFlags::ALL`])
+    ///
+    /// - [`NsFlags::USER`] is added if [`NsFlags::DISABLE_USER_NS`] is present (even with
+    ///   [`NsFlags::ALL`], which only tries to create one), since bwrap can only prevent the
+    ///   creation of user namespaces from a new one. This isn't the case when joining an existing
+    ///   user namespace (see [`crate::BwrapCommand::userns`]). [`NsFlags::ASSERT_DISABLE_USER_NS`]
+    ///   is left as-is, it is meant to check a joined user namespace
     #[must_use]
-    pub fn sanitize(mut self) -> Self {
+    pub fn sanitize(self) -> Self {
+        self.sanitize_with(false)
+    }
+
+    /// Same as [`NsFlags::sanitize`], `joins_userns` telling if an existing user namespace is
+    /// joined
+    fn sanitize_with(mut self, joins_userns: bool) -> Self {
+        if self.contains(Self::NET) {
+            self.remove(Self::SHARE_NET);
+        }
+        if self.contains(Self::AS_PID_1) && !self.contains(Self::ALL) {
+            self.insert(Self::PID);
+        }
         if self.contains(Self::ALL) {
             self.remove(
                 Self::USER
@@ -132,6 +182,9 @@ impl NsFlags {
                     | Self::CGROUPS_TRY,
             );
         }
+        if self.contains(Self::DISABLE_USER_NS) && !joins_userns {
+            self.insert(Self::USER);
+        }
         if self.contains(Self::USER) {
             self.remove(Self::USER_TRY);
         }
@@ -159,10 +212,11 @@ impl NsFlags {
                 Self::PID => "--unshare-pid",
                 Self::UTS => "--unshare-uts",
                 Self::DISABLE_USER_NS => "--disable-userns",
-                Self::ASSERT_DISABLE_USER_NS => "--assert-disable-userns",
+                Self::ASSERT_DISABLE_USER_NS => "--assert-userns-disabled",
                 Self::ALL => "--unshare-all",
                 Self::NEW_SESSION => "--new-session",
                 Self::DIE_WITH_PARENT => "--die-with-parent",
+                Self::AS_PID_1 => "--as-pid-1",
                 _ => continue,
             }));
         }
@@ -171,10 +225,79 @@ impl NsFlags {
     }
 }
 
+/// The namespaces that can be inspected with `/proc/<pid>/ns/<name>`
+const PROC_NAMESPACES: &[(NsFlags, &str)] = &[
+    (NsFlags::USER, "user"),
+    (NsFlags::IPC, "ipc"),
+    (NsFlags::PID, "pid"),
+    (NsFlags::NET, "net"),
+    (NsFlags::UTS, "uts"),
+    (NsFlags::CGROUPS, "cgroup"),
+];
+
+impl NsFlags {
+    /// Find which namespaces of the process `pid` differ from the ones of the current process.
+    ///
+    /// Given the pid of the sandboxed program, this returns the namespaces that were effectively
+    /// unshared, which can differ from the requested ones when using the `_TRY` variants.
+    ///
+    /// # Errors
+    ///
+    /// This fails if the namespaces of the process can't be read (for example if the process
+    /// exited already)
+    pub fn unshared_by(pid: u32) -> std::io::Result<Self> {
+        let mut flags = Self::empty();
+        for &(flag, name) in PROC_NAMESPACES {
+            let own = std::fs::read_link(format!("/proc/self/ns/{name}"))?;
+            let other = std::fs::read_link(format!("/proc/{pid}/ns/{name}"))?;
+            if own != other {
+                flags.insert(flag);
+            }
+        }
+        Ok(flags)
+    }
+
+    /// Find which of the requested namespaces can't be created on this host, because the kernel
+    /// doesn't support them (no `/proc/self/ns/<name>`) or, for user namespaces, because they
+    /// are disabled (`/proc/sys/user/max_user_namespaces` is 0).
+    ///
+    /// Only the mandatory flags are checked ([`NsFlags::ALL`] counting as every namespace it
+    /// mandates), the `_TRY` variants never fail. The returned flags can then be replaced by their
+    /// `_TRY` variant, or dropped.
+    #[must_use]
+    pub fn unsupported_on_host(&self) -> Self {
+        self.unsupported_with(|name| {
+            if !Path::new("/proc/self/ns").join(name).exists() {
+                return false;
+            }
+            name != "user"
+                || std::fs::read_to_string("/proc/sys/user/max_user_namespaces")
+                    .map_or(true, |max| max.trim() != "0")
+        })
+    }
+
+    /// Same as [`NsFlags::unsupported_on_host`], with `available` telling if a namespace (by its
+    /// name in `/proc/<pid>/ns`) can be created
+    fn unsupported_with(self, available: impl Fn(&str) -> bool) -> Self {
+        let mut requested = self;
+        if requested.contains(Self::ALL) {
+            requested |= Self::IPC | Self::PID | Self::NET | Self::UTS;
+        }
+        let mut unsupported = Self::empty();
+        for &(flag, name) in PROC_NAMESPACES {
+            if requested.contains(flag) && !available(name) {
+                unsupported.insert(flag);
+            }
+        }
+        unsupported
+    }
+}
+
 bitflags! {
     /// The flags that takes no option that mananage what the sandbox shares/doesn't share with the
     /// host
     #[derive(Debug, Clone,Copy, Eq, PartialEq, Hash, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct NsFlags: u32 {
         /// --unshare-user
         const USER = 1 << 0;
@@ -209,6 +332,8 @@ bitflags! {
         const DIE_WITH_PARENT = 1 << 12;
         /// --new-session
         const NEW_SESSION = 1 << 13;
+        /// --as-pid-1
+        const AS_PID_1 = 1 << 14;
     }
 }
 
@@ -248,6 +373,47 @@ mod test {
         assert_eq!(flags.sanitize(), F::ALL | F::SHARE_NET);
     }
 
+    #[test]
+    fn net() {
+        assert_eq!((F::NET | F::SHARE_NET).sanitize(), F::NET);
+        assert_eq!(
+            (F::NET | F::SHARE_NET).to_options().collect::<Vec<_>>(),
+            vec!["--unshare-net"]
+        );
+        assert_eq!((F::ALL | F::NET | F::SHARE_NET).sanitize(), F::ALL);
+        assert_eq!((F::ALL | F::SHARE_NET).sanitize(), F::ALL | F::SHARE_NET);
+    }
+
+    #[test]
+    fn disable_userns() {
+        assert_eq!(F::DISABLE_USER_NS.sanitize(), F::DISABLE_USER_NS | F::USER);
+        assert_eq!(
+            (F::DISABLE_USER_NS | F::USER_TRY).sanitize(),
+            F::DISABLE_USER_NS | F::USER
+        );
+        assert_eq!(
+            (F::DISABLE_USER_NS | F::ALL)
+                .to_options()
+                .collect::<Vec<_>>(),
+            vec!["--unshare-user", "--unshare-all", "--disable-userns"]
+        );
+        assert_eq!(F::DISABLE_USER_NS.sanitize_with(true), F::DISABLE_USER_NS);
+        assert_eq!(
+            F::ASSERT_DISABLE_USER_NS.sanitize(),
+            F::ASSERT_DISABLE_USER_NS
+        );
+        assert_eq!(
+            F::ASSERT_DISABLE_USER_NS.to_options().collect::<Vec<_>>(),
+            vec!["--assert-userns-disabled"]
+        );
+    }
+
+    #[test]
+    fn as_pid_1() {
+        assert_eq!(F::AS_PID_1.sanitize(), F::AS_PID_1 | F::PID);
+        assert_eq!((F::AS_PID_1 | F::ALL).sanitize(), F::AS_PID_1 | F::ALL);
+    }
+
     #[test]
     fn args_all() {
         let mut flags = F::empty();
@@ -261,6 +427,40 @@ mod test {
         );
     }
 
+    #[test]
+    fn unshared_by_self() {
+        assert_eq!(F::unshared_by(std::process::id()).unwrap(), F::empty());
+    }
+
+    #[test]
+    #[ignore = "requires the privilege to create namespaces"]
+    fn unshared_by_child() {
+        let mut child = std::process::Command::new("unshare")
+            .args(["--net", "--uts", "sleep", "5"])
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let flags = F::unshared_by(child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(flags.unwrap(), F::NET | F::UTS);
+    }
+
+    #[test]
+    fn unsupported() {
+        let no_net = |name: &str| name != "net" && name != "user";
+        assert_eq!(
+            (F::NET | F::PID | F::USER_TRY).unsupported_with(no_net),
+            F::NET
+        );
+        assert_eq!(
+            (F::ALL | F::USER).unsupported_with(no_net),
+            F::NET | F::USER
+        );
+        assert_eq!((F::UTS | F::IPC).unsupported_with(no_net), F::empty());
+        assert_eq!(F::NET.unsupported_with(|_| true), F::empty());
+    }
+
     #[test]
     fn args1() {
         let mut flags = F::empty();
@@ -0,0 +1,122 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::FsOptions;
+
+/// A mount as described in the `mounts` array of an OCI runtime `config.json`
+///
+/// Only a subset of the mounts can be mapped into bwrap options, see
+/// [`crate::BwrapCommand::from_oci_mounts`]
+#[derive(Debug, Clone, Default)]
+pub struct OciMount {
+    /// Where the mount will live inside of the sandbox
+    pub destination: PathBuf,
+    /// The `type` of the mount (`bind`, `tmpfs`, ...)
+    pub kind: Option<String>,
+    /// The source of the mount, for binds this is the path on the host
+    pub source: Option<PathBuf>,
+    /// The mount options (`ro`, `rbind`, `size=64m`, `mode=1777`, ...)
+    pub options: Vec<String>,
+}
+
+impl OciMount {
+    fn has_option(&self, option: &str) -> bool {
+        self.options.iter().any(|o| o == option)
+    }
+
+    fn option_value(&self, key: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find_map(|o| o.strip_prefix(key)?.strip_prefix('='))
+    }
+
+    fn is_bind(&self) -> bool {
+        self.kind.as_deref() == Some("bind") || self.has_option("bind") || self.has_option("rbind")
+    }
+
+    /// Convert the mount into the equivalent [`FsOptions`], or `None` if the mount isn't supported
+    pub(crate) fn to_fs_option<'fd>(&self) -> Option<FsOptions<'fd>> {
+        let destination = OsString::from(&self.destination);
+        if self.is_bind() {
+            let source = self.source.as_ref()?;
+            return Some(FsOptions::Bind {
+                read_only: self.has_option("ro"),
+                source: OsString::from(source),
+                destination,
+                permission: None,
+                try_: false,
+            });
+        }
+        match self.kind.as_deref() {
+            Some("tmpfs") => Some(FsOptions::TempFs {
+                destination,
+                permission: self
+                    .option_value("mode")
+                    .and_then(|m| u64::from_str_radix(m, 8).ok()),
+                size: self.option_value("size").and_then(parse_size),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a tmpfs size in the `mount(8)` format (with an optional `k`, `m` or `g` suffix)
+///
+/// Sizes given as a percentage of the memory can't be represented and return `None`
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, multiplier) = match size.chars().last()?.to_ascii_lowercase() {
+        'k' => (&size[..size.len() - 1], 1 << 10),
+        'm' => (&size[..size.len() - 1], 1 << 20),
+        'g' => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OciMount;
+
+    #[test]
+    fn mounts() {
+        let mounts = [
+            OciMount {
+                destination: "/data".into(),
+                kind: Some("bind".into()),
+                source: Some("/srv/data".into()),
+                options: vec!["rbind".into()],
+            },
+            OciMount {
+                destination: "/proc".into(),
+                kind: Some("proc".into()),
+                source: Some("proc".into()),
+                options: vec![],
+            },
+            OciMount {
+                destination: "/tmp".into(),
+                kind: Some("tmpfs".into()),
+                source: Some("tmpfs".into()),
+                options: vec!["nosuid".into(), "mode=1777".into(), "size=64k".into()],
+            },
+        ];
+        let args = crate::BwrapCommand::from_oci_mounts("echo", &mounts)
+            .build_args()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--bind",
+                "/srv/data",
+                "/data",
+                "--perms",
+                "1777",
+                "--size",
+                "65536",
+                "--tmpfs",
+                "/tmp",
+                "--",
+                "echo"
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Random builder calls fed through [`BwrapCommand::build_args`], checking that building never
+//! panics and that the program always comes right after the `--` separator
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStringExt;
+
+use proptest::prelude::*;
+
+use crate::{BwrapCommand, NsFlags};
+
+/// A call to one of the builder methods
+#[derive(Debug, Clone)]
+enum Op {
+    ClearEnv(bool),
+    AddEnv(OsString, OsString),
+    UnsetEnv(OsString),
+    Bind {
+        host: OsString,
+        guest: OsString,
+        read_only: bool,
+        try_: bool,
+    },
+    BindWithPerm(OsString, OsString, u64),
+    Tmpfs(OsString, Option<usize>),
+    Dir(OsString),
+    Chmod(OsString, u64),
+    Symlink(OsString, OsString),
+    LockFile(OsString),
+    RemountRo(OsString),
+    OverlaySrc(OsString),
+    TmpOverlay(OsString, Vec<OsString>),
+    RoOverlay(OsString, Vec<OsString>),
+    Overlay(OsString, Vec<OsString>, OsString, OsString),
+    Namespaces(u32),
+    Cwd(OsString),
+    CapAdd(OsString),
+    CapDrop(OsString),
+    AddToPath(OsString),
+    Arg(OsString),
+}
+
+/// Any bytes, including the empty string and invalid UTF-8
+fn os_string() -> impl Strategy<Value = OsString> {
+    prop::collection::vec(any::<u8>(), 0..16).prop_map(OsString::from_vec)
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        any::<bool>().prop_map(Op::ClearEnv),
+        (os_string(), os_string()).prop_map(|(k, v)| Op::AddEnv(k, v)),
+        os_string().prop_map(Op::UnsetEnv),
+        (os_string(), os_string(), any::<bool>(), any::<bool>()).prop_map(
+            |(host, guest, read_only, try_)| Op::Bind {
+                host,
+                guest,
+                read_only,
+                try_
+            }
+        ),
+        (os_string(), os_string(), any::<u64>()).prop_map(|(h, g, p)| Op::BindWithPerm(h, g, p)),
+        (os_string(), any::<Option<usize>>()).prop_map(|(p, s)| Op::Tmpfs(p, s)),
+        os_string().prop_map(Op::Dir),
+        (os_string(), any::<u64>()).prop_map(|(p, m)| Op::Chmod(p, m)),
+        (os_string(), os_string()).prop_map(|(s, d)| Op::Symlink(s, d)),
+        os_string().prop_map(Op::LockFile),
+        os_string().prop_map(Op::RemountRo),
+        os_string().prop_map(Op::OverlaySrc),
+        (os_string(), prop::collection::vec(os_string(), 0..4))
+            .prop_map(|(d, s)| Op::TmpOverlay(d, s)),
+        (os_string(), prop::collection::vec(os_string(), 0..4))
+            .prop_map(|(d, s)| Op::RoOverlay(d, s)),
+        (
+            os_string(),
+            prop::collection::vec(os_string(), 0..4),
+            os_string(),
+            os_string()
+        )
+            .prop_map(|(d, s, rw, w)| Op::Overlay(d, s, rw, w)),
+        any::<u32>().prop_map(Op::Namespaces),
+        os_string().prop_map(Op::Cwd),
+        os_string().prop_map(Op::CapAdd),
+        os_string().prop_map(Op::CapDrop),
+        os_string().prop_map(Op::AddToPath),
+        os_string().prop_map(Op::Arg),
+    ]
+}
+
+fn apply(builder: &mut BwrapCommand<'_>, op: Op) {
+    use std::path::PathBuf;
+
+    match op {
+        Op::ClearEnv(clear) => builder.clear_env(clear),
+        Op::AddEnv(key, value) => builder.add_env(key, value),
+        Op::UnsetEnv(key) => builder.add_unset_env(key),
+        Op::Bind {
+            host,
+            guest,
+            read_only,
+            try_,
+        } => match (read_only, try_) {
+            (false, false) => builder.bind(host, guest),
+            (false, true) => builder.try_bind(host, guest),
+            (true, false) => builder.bind_read_only(host, guest),
+            (true, true) => builder.try_bind_ready_only(host, guest),
+        },
+        Op::BindWithPerm(host, guest, perm) => builder.bind_with_perm(host, guest, perm),
+        Op::Tmpfs(path, None) => builder.tmpfs(path),
+        Op::Tmpfs(path, Some(size)) => builder.tmpfs_with_size(path, size),
+        Op::Dir(path) => builder.dir(path),
+        Op::Chmod(path, perm) => builder.chmod(path, perm),
+        Op::Symlink(source, destination) => builder.symlink(source, destination),
+        Op::LockFile(path) => builder.lock_file(path),
+        Op::RemountRo(path) => builder.remount_ro(path),
+        Op::OverlaySrc(path) => builder.overlay_src(path),
+        Op::TmpOverlay(destination, sources) => {
+            builder.tmp_overlay(destination, sources.into_iter().map(PathBuf::from))
+        }
+        Op::RoOverlay(destination, sources) => {
+            builder.ro_overlay(destination, sources.into_iter().map(PathBuf::from))
+        }
+        Op::Overlay(destination, sources, rwsrc, workdir) => builder.overlay(
+            destination,
+            sources.into_iter().map(PathBuf::from),
+            rwsrc,
+            workdir,
+        ),
+        Op::Namespaces(bits) => builder.add_namespace_flags(NsFlags::from_bits_truncate(bits)),
+        Op::Cwd(path) => builder.set_cwd(path),
+        Op::CapAdd(cap) => builder.cap_add(cap),
+        Op::CapDrop(cap) => builder.cap_drop(cap),
+        Op::AddToPath(path) => builder.add_to_path(path),
+        Op::Arg(arg) => builder.arg(arg),
+    };
+}
+
+/// Build the arguments of the given calls, checking the tail of the arguments
+fn check(program: &OsStr, ops: Vec<Op>) {
+    let args = ops
+        .iter()
+        .filter_map(|op| match op {
+            Op::Arg(arg) => Some(arg.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut builder = BwrapCommand::new(program);
+    for op in ops {
+        apply(&mut builder, op);
+    }
+    let Ok(built) = builder.build_args() else {
+        return;
+    };
+    let tail = built.len() - args.len() - 2;
+    assert_eq!(built[tail], "--");
+    assert_eq!(built[tail + 1], program);
+    assert_eq!(built[tail + 2..], args);
+}
+
+proptest! {
+    #[test]
+    fn build_args_never_panics(
+        program in os_string(),
+        ops in prop::collection::vec(op(), 0..32),
+    ) {
+        check(&program, ops);
+    }
+}
+
+#[test]
+fn regressions() {
+    // empty strings everywhere
+    check(
+        OsStr::new(""),
+        vec![
+            Op::AddEnv(OsString::new(), OsString::new()),
+            Op::Bind {
+                host: OsString::new(),
+                guest: OsString::new(),
+                read_only: true,
+                try_: false,
+            },
+            Op::Cwd(OsString::new()),
+            Op::Arg(OsString::new()),
+        ],
+    );
+    // the biggest permission and size
+    check(
+        OsStr::new("ls"),
+        vec![
+            Op::Chmod("/".into(), u64::MAX),
+            Op::BindWithPerm("/".into(), "/".into(), u64::MAX),
+            Op::Tmpfs("/tmp".into(), Some(usize::MAX)),
+        ],
+    );
+    // every namespace flag, including the unknown bits
+    check(OsStr::new("ls"), vec![Op::Namespaces(u32::MAX)]);
+    // an argument that looks like the separator
+    check(
+        OsStr::new("--"),
+        vec![Op::Arg("--".into()), Op::Arg("--".into())],
+    );
+    // overlay layers without any overlay, and an overlay without layers
+    check(
+        OsStr::new("ls"),
+        vec![
+            Op::TmpOverlay("/usr".into(), Vec::new()),
+            Op::OverlaySrc("/usr".into()),
+        ],
+    );
+}
@@ -0,0 +1,100 @@
+//! Helpers used by the `serde` feature.
+//!
+//! The fds are serialized as their raw number, which only makes sense in the process that owns
+//! them, and can't be deserialized: anything holding one fails to deserialize (a missing or null
+//! fd is accepted).
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::os::fd::{AsRawFd, BorrowedFd};
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+#[expect(
+    clippy::trivially_copy_pass_by_ref,
+    reason = "the signature is imposed by serde"
+)]
+pub(crate) fn fd<S: Serializer>(fd: &BorrowedFd<'_>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i32(fd.as_raw_fd())
+}
+
+#[expect(
+    clippy::ref_option,
+    clippy::trivially_copy_pass_by_ref,
+    reason = "the signature is imposed by serde"
+)]
+pub(crate) fn option_fd<S: Serializer>(
+    fd: &Option<BorrowedFd<'_>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match fd {
+        Some(fd) => serializer.serialize_some(&fd.as_raw_fd()),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub(crate) fn fds<S: Serializer>(fds: &[BorrowedFd<'_>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(fds.iter().map(AsRawFd::as_raw_fd))
+}
+
+const FD_ERROR: &str = "fds can't be deserialized";
+
+pub(crate) fn deserialize_fd<'de, 'fd, D: Deserializer<'de>>(
+    _deserializer: D,
+) -> Result<BorrowedFd<'fd>, D::Error> {
+    Err(serde::de::Error::custom(FD_ERROR))
+}
+
+pub(crate) fn deserialize_option_fd<'de, 'fd, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<BorrowedFd<'fd>>, D::Error> {
+    match Option::<i32>::deserialize(deserializer)? {
+        Some(_) => Err(serde::de::Error::custom(FD_ERROR)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn deserialize_fds<'de, 'fd, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<BorrowedFd<'fd>>, D::Error> {
+    if Vec::<i32>::deserialize(deserializer)?.is_empty() {
+        Ok(Vec::new())
+    } else {
+        Err(serde::de::Error::custom(FD_ERROR))
+    }
+}
+
+/// Deserialize the `(flag, capability)` pairs of [`crate::BwrapCommand`], the flag being either
+/// `--cap-add` or `--cap-drop`
+pub(crate) fn capabilities<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(&'static str, OsString)>, D::Error> {
+    Vec::<(String, OsString)>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(flag, cap)| match flag.as_str() {
+            "--cap-add" => Ok(("--cap-add", cap)),
+            "--cap-drop" => Ok(("--cap-drop", cap)),
+            _ => Err(serde::de::Error::unknown_variant(
+                &flag,
+                &["--cap-add", "--cap-drop"],
+            )),
+        })
+        .collect()
+}
+
+/// Serialize the environment as a list of `(key, value)` pairs, since [`OsString`] keys can't be
+/// used as map keys in every format (such as JSON)
+pub(crate) fn env<S: Serializer>(
+    env: &BTreeMap<OsString, OsString>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(env)
+}
+
+pub(crate) fn deserialize_env<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<OsString, OsString>, D::Error> {
+    Ok(Vec::<(OsString, OsString)>::deserialize(deserializer)?
+        .into_iter()
+        .collect())
+}
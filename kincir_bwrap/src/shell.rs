@@ -0,0 +1,64 @@
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+
+/// Quote `arg` so that a POSIX-like shell reads it back as the exact same bytes.
+///
+/// Simple arguments are left untouched, the printable UTF-8 ones are single-quoted, and the
+/// others (non-printable characters or invalid UTF-8) use the `$'...'` quoting of bash/zsh
+pub(crate) fn quote(arg: &OsStr) -> String {
+    let bytes = arg.as_bytes();
+    let is_plain = |b: &u8| b.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(b);
+    if !bytes.is_empty() && bytes.iter().all(is_plain) {
+        // only ASCII bytes
+        return arg.to_string_lossy().into_owned();
+    }
+    if let Some(arg) = arg
+        .to_str()
+        .filter(|arg| !arg.chars().any(char::is_control))
+    {
+        return format!("'{}'", arg.replace('\'', r"'\''"));
+    }
+    let mut out = String::from("$'");
+    for &byte in bytes {
+        match byte {
+            b'\\' => out.push_str(r"\\"),
+            b'\'' => out.push_str(r"\'"),
+            b'\n' => out.push_str(r"\n"),
+            b'\t' => out.push_str(r"\t"),
+            b' '..=b'~' => out.push(char::from(byte)),
+            // writing into a `String` can't fail
+            _ => write!(out, "\\x{byte:02x}").unwrap(),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn plain() {
+        assert_eq!(quote(OsStr::new("--ro-bind")), "--ro-bind");
+        assert_eq!(quote(OsStr::new("/usr/lib64")), "/usr/lib64");
+    }
+
+    #[test]
+    fn quoted() {
+        assert_eq!(quote(OsStr::new("")), "''");
+        assert_eq!(quote(OsStr::new("some value")), "'some value'");
+        assert_eq!(quote(OsStr::new("it's")), r"'it'\''s'");
+        assert_eq!(quote(OsStr::new("$HOME")), "'$HOME'");
+        assert_eq!(quote(OsStr::new("été")), "'été'");
+    }
+
+    #[test]
+    fn escaped() {
+        assert_eq!(quote(OsStr::new("a\nb")), r"$'a\nb'");
+        assert_eq!(quote(OsStr::from_bytes(b"it's \xff")), r"$'it\'s \xff'");
+        assert_eq!(quote(OsStr::from_bytes(b"\x1b[0m\\")), r"$'\x1b[0m\\'");
+    }
+}